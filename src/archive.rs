@@ -0,0 +1,358 @@
+use std::fs::{create_dir_all, read, write};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use flate2::{Compression, Crc};
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use tar::{Archive, Builder, EntryType, Header};
+
+use crate::arguments::SourceEntry;
+use crate::boilerplates::{ContentOptions, read_text_lines};
+use crate::handlers::{file_mode, install_relative_path, is_text_file};
+use crate::minify::minify_lines;
+
+// Packs a '-d' directory into one compressed archive at build time, so the bad USB script only
+// has to type the archive as base64 instead of every line of every file. Unix targets get a
+// .tar.gz, Windows targets a .zip since that is what Expand-Archive understands.
+
+//Packs the entries into a gzip compressed tar archive, with paths relative to the install
+//directory like install_relative_path() gives them.
+pub fn pack_tar_gz(entries: &[SourceEntry], source_root: &Path, keep_prefix: bool, content: &ContentOptions) -> io::Result<Vec<u8>> {
+    let encoder = GzEncoder::new(Vec::new(), Compression::best());
+    let mut builder = Builder::new(encoder);
+    for entry in entries {
+        match entry {
+            SourceEntry::File(path) | SourceEntry::Binary(path) => {
+                let install_path = install_relative_path(path, source_root, keep_prefix);
+                let contents : Vec<u8> = file_contents(path, content)?;
+                let mut header = new_header(path, EntryType::Regular, contents.len() as u64, content);
+                builder.append_data(&mut header, &install_path, contents.as_slice())?;
+            },
+            SourceEntry::EmptyDir(path) => {
+                let install_path = install_relative_path(path, source_root, keep_prefix);
+                let mut header = new_header(path, EntryType::Directory, 0, content);
+                builder.append_data(&mut header, &install_path, io::empty())?;
+            },
+            SourceEntry::Symlink(path, link_target) => {
+                let install_path = install_relative_path(path, source_root, keep_prefix);
+                let mut header = new_header(path, EntryType::Symlink, 0, content);
+                builder.append_link(&mut header, &install_path, link_target)?;
+            },
+        }
+    }
+    builder.into_inner()?.finish()
+}
+
+//What goes into the archive for a file. Text files get their placeholders filled in and are
+//minified like they would when typed, everything else goes in as it is.
+pub fn file_contents(path: &Path, content: &ContentOptions) -> io::Result<Vec<u8>> {
+    if (content.template.is_none() && !content.minify) || !is_text_file(path)? {
+        return read(path)
+    }
+    let mut lines : Vec<String> = match read_text_lines(path, content) {
+        Ok(x) => x,
+        Err(e) => return Err(io::Error::other(format!("{:?}", e))),
+    };
+    if content.minify && let Some(minified) = minify_lines(path, &lines) {
+        lines = minified;
+    }
+    Ok(lines.iter().map(|x| format!("{}\n", x)).collect::<String>().into_bytes())
+}
+
+//A tar header that keeps the permissions and modification time of the source, but none of the
+//owner information of this machine. '--mode' overrides the permissions of files.
+fn new_header(path: &Path, entry_type: EntryType, size: u64, content: &ContentOptions) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(size);
+    let mode : Option<u32> = match entry_type {
+        EntryType::Symlink => Some(0o777),
+        EntryType::Directory => file_mode(path),
+        _ => content.modes.mode(path),
+    };
+    header.set_mode(mode.unwrap_or(if entry_type == EntryType::Directory {0o755} else {0o644}));
+    header.set_mtime(modified_seconds(path));
+    header.set_uid(0);
+    header.set_gid(0);
+    header
+}
+
+fn modified_seconds(path: &Path) -> u64 {
+    path.symlink_metadata().ok()
+        .and_then(|x| x.modified().ok())
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+//Packs the entries into a zip archive. Symlinks never get here for Windows targets, they are
+//copied while walking the directory.
+pub fn pack_zip(entries: &[SourceEntry], source_root: &Path, keep_prefix: bool, content: &ContentOptions) -> io::Result<Vec<u8>> {
+    let mut archive : Vec<u8> = Vec::new();
+    let mut central_directory : Vec<u8> = Vec::new();
+    let mut count : usize = 0;
+    for entry in entries {
+        let (path, is_dir) = match entry {
+            SourceEntry::File(path) | SourceEntry::Binary(path) => (path, false),
+            SourceEntry::EmptyDir(path) => (path, true),
+            SourceEntry::Symlink(path, _target) => {eprintln!("Can't put symlink {} in a zip, skipping it.", path.display()); continue},
+        };
+        let install_path = install_relative_path(path, source_root, keep_prefix);
+        let mut name : String = install_path.components().map(|x| x.as_os_str().to_string_lossy().into_owned()).collect::<Vec<String>>().join("/");
+        let uncompressed : Vec<u8> = if is_dir {Vec::new()} else {file_contents(path, content)?};
+        let (contents, method) : (Vec<u8>, u16) = if is_dir {
+            name.push('/');
+            (Vec::new(), 0)
+        } else {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(&uncompressed)?;
+            (encoder.finish()?, 8)
+        };
+        let mut crc = Crc::new();
+        crc.update(&uncompressed);
+        let (time, date) : (u16, u16) = dos_date_time(modified_seconds(path));
+        let offset : u32 = zip_field(archive.len(), "offset of", &name)?;
+        let compressed_size : u32 = zip_field(contents.len(), "compressed size of", &name)?;
+        let size : u32 = zip_field(uncompressed.len(), "size of", &name)?;
+        let name_length : u16 = zip_field(name.len(), "name length of", &name)?;
+
+        //Local file header, bit 11 of the flags says the name is UTF-8.
+        archive.write_all(&0x04034b50u32.to_le_bytes())?;
+        archive.write_all(&20u16.to_le_bytes())?;
+        archive.write_all(&0x0800u16.to_le_bytes())?;
+        archive.write_all(&method.to_le_bytes())?;
+        archive.write_all(&time.to_le_bytes())?;
+        archive.write_all(&date.to_le_bytes())?;
+        archive.write_all(&crc.sum().to_le_bytes())?;
+        archive.write_all(&compressed_size.to_le_bytes())?;
+        archive.write_all(&size.to_le_bytes())?;
+        archive.write_all(&name_length.to_le_bytes())?;
+        archive.write_all(&0u16.to_le_bytes())?;
+        archive.write_all(name.as_bytes())?;
+        archive.write_all(&contents)?;
+
+        central_directory.write_all(&0x02014b50u32.to_le_bytes())?;
+        central_directory.write_all(&20u16.to_le_bytes())?;
+        central_directory.write_all(&20u16.to_le_bytes())?;
+        central_directory.write_all(&0x0800u16.to_le_bytes())?;
+        central_directory.write_all(&method.to_le_bytes())?;
+        central_directory.write_all(&time.to_le_bytes())?;
+        central_directory.write_all(&date.to_le_bytes())?;
+        central_directory.write_all(&crc.sum().to_le_bytes())?;
+        central_directory.write_all(&compressed_size.to_le_bytes())?;
+        central_directory.write_all(&size.to_le_bytes())?;
+        central_directory.write_all(&name_length.to_le_bytes())?;
+        //Extra field, comment, disk number and internal attributes are all empty.
+        central_directory.write_all(&[0u8; 8])?;
+        let external_attributes : u32 = if is_dir {0x10} else {0};
+        central_directory.write_all(&external_attributes.to_le_bytes())?;
+        central_directory.write_all(&offset.to_le_bytes())?;
+        central_directory.write_all(name.as_bytes())?;
+        count += 1;
+    }
+    let count : u16 = zip_field(count, "number of entries in", "the archive")?;
+    let central_size : u32 = zip_field(central_directory.len(), "central directory of", "the archive")?;
+    let central_offset : u32 = zip_field(archive.len(), "central directory offset of", "the archive")?;
+    archive.write_all(&central_directory)?;
+    archive.write_all(&0x06054b50u32.to_le_bytes())?;
+    archive.write_all(&[0u8; 4])?;
+    archive.write_all(&count.to_le_bytes())?;
+    archive.write_all(&count.to_le_bytes())?;
+    archive.write_all(&central_size.to_le_bytes())?;
+    archive.write_all(&central_offset.to_le_bytes())?;
+    archive.write_all(&0u16.to_le_bytes())?;
+    Ok(archive)
+}
+
+//Plain zip archives only have 16 and 32 bit fields, anything bigger would need zip64 so it is an
+//error instead of a silently truncated number.
+fn zip_field<T: TryFrom<usize>>(value: usize, what: &str, name: &str) -> io::Result<T> {
+    T::try_from(value).map_err(|_| io::Error::other(format!("the {} {} is too big for a zip archive", what, name)))
+}
+
+//What came out of an archive, with paths relative to the directory it was unpacked in.
+pub enum Unpacked {
+    File(PathBuf),
+    Dir(PathBuf),
+    //Links are only reported, never created, so nothing can point outside of the directory.
+    Symlink(PathBuf, PathBuf),
+}
+
+//Unpacks a .tar.gz like pack_tar_gz() writes into the directory.
+pub fn unpack_tar_gz(data: &[u8], dir: &Path) -> io::Result<Vec<Unpacked>> {
+    let mut unpacked : Vec<Unpacked> = Vec::new();
+    let mut archive = Archive::new(GzDecoder::new(data));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path : PathBuf = plain_path(&entry.path()?);
+        match entry.header().entry_type() {
+            EntryType::Symlink => unpacked.push(Unpacked::Symlink(path, entry.link_name()?.unwrap_or_default().into_owned())),
+            EntryType::Directory => {create_dir_all(dir.join(&path))?; unpacked.push(Unpacked::Dir(path))},
+            _ => {
+                if let Some(parent) = dir.join(&path).parent() {
+                    create_dir_all(parent)?;
+                }
+                let mut contents : Vec<u8> = Vec::new();
+                entry.read_to_end(&mut contents)?;
+                write(dir.join(&path), contents)?;
+                unpacked.push(Unpacked::File(path));
+            },
+        }
+    }
+    Ok(unpacked)
+}
+
+//Unpacks a zip like pack_zip() writes into the directory, by walking the local file headers.
+pub fn unpack_zip(data: &[u8], dir: &Path) -> io::Result<Vec<Unpacked>> {
+    let broken = || io::Error::new(io::ErrorKind::InvalidData, "broken zip archive");
+    let number = |at: usize, size: usize| -> io::Result<usize> {
+        let bytes : &[u8] = data.get(at..at + size).ok_or_else(broken)?;
+        Ok(bytes.iter().rev().fold(0, |x, y| (x << 8) | *y as usize))
+    };
+    let mut unpacked : Vec<Unpacked> = Vec::new();
+    let mut offset : usize = 0;
+    while number(offset, 4).ok() == Some(0x04034b50) {
+        let method : usize = number(offset + 8, 2)?;
+        let compressed_size : usize = number(offset + 18, 4)?;
+        let name_length : usize = number(offset + 26, 2)?;
+        let extra_length : usize = number(offset + 28, 2)?;
+        let name_start : usize = offset + 30;
+        let data_start : usize = name_start + name_length + extra_length;
+        let name : String = String::from_utf8_lossy(data.get(name_start..name_start + name_length).ok_or_else(broken)?).into_owned();
+        let contents : &[u8] = data.get(data_start..data_start + compressed_size).ok_or_else(broken)?;
+        let path : PathBuf = plain_path(Path::new(&name.replace('\\', "/")));
+        if name.ends_with('/') {
+            create_dir_all(dir.join(&path))?;
+            unpacked.push(Unpacked::Dir(path));
+        } else {
+            let mut uncompressed : Vec<u8> = Vec::new();
+            match method {
+                0 => uncompressed.extend_from_slice(contents),
+                8 => {DeflateDecoder::new(contents).read_to_end(&mut uncompressed)?;},
+                _ => return Err(io::Error::new(io::ErrorKind::Unsupported, format!("unknown zip compression method {}", method))),
+            }
+            if let Some(parent) = dir.join(&path).parent() {
+                create_dir_all(parent)?;
+            }
+            write(dir.join(&path), uncompressed)?;
+            unpacked.push(Unpacked::File(path));
+        }
+        offset = data_start + compressed_size;
+    }
+    Ok(unpacked)
+}
+
+//Only the plain components of a path from an archive, so nothing gets unpacked outside of the
+//directory.
+fn plain_path(path: &Path) -> PathBuf {
+    path.components().filter_map(|x| match x {Component::Normal(y) => Some(y), _ => None}).collect()
+}
+
+//Zip files store times the way MS-DOS did, which can't go before 1980.
+fn dos_date_time(unix_seconds: u64) -> (u16, u16) {
+    let (year, month, day, hour, minute, second) = civil_from_unix(unix_seconds);
+    if year < 1980 {
+        return (0, 0x21)
+    }
+    let time : u16 = ((hour << 11) | (minute << 5) | (second / 2)) as u16;
+    let date : u16 = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    (time, date)
+}
+
+//Turns seconds since 1970 into year, month, day, hour, minute and second (UTC), using Howard
+//Hinnant's days_from_civil algorithm the other way around.
+pub fn civil_from_unix(unix_seconds: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let days : i64 = (unix_seconds / 86400) as i64 + 719468;
+    let seconds_of_day : u64 = unix_seconds % 86400;
+    let era : i64 = days.div_euclid(146097);
+    let day_of_era : i64 = days - era * 146097;
+    let year_of_era : i64 = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year : i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index : i64 = (5 * day_of_year + 2) / 153;
+    let day : i64 = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month : i64 = if month_index < 10 {month_index + 3} else {month_index - 9};
+    let year : i64 = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    (year as u64, month as u64, day as u64, seconds_of_day / 3600, seconds_of_day % 3600 / 60, seconds_of_day % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{read_to_string, remove_dir_all};
+    use std::process::{self, Command};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir : PathBuf = env::temp_dir().join(format!("busbi-test-{}-{}", name, process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sample_entries(root: &Path) -> Vec<SourceEntry> {
+        create_dir_all(root.join("sub")).unwrap();
+        create_dir_all(root.join("empty")).unwrap();
+        write(root.join("a.txt"), "first\nsecond\n").unwrap();
+        write(root.join("sub/b.bin"), [0u8, 159, 146, 150, 255]).unwrap();
+        vec![
+            SourceEntry::File(root.join("a.txt")),
+            SourceEntry::Binary(root.join("sub/b.bin")),
+            SourceEntry::EmptyDir(root.join("empty")),
+        ]
+    }
+
+    #[test]
+    fn zip_can_be_listed_by_unzip() {
+        let dir : PathBuf = test_dir("unzip");
+        let root : PathBuf = dir.join("src");
+        let entries : Vec<SourceEntry> = sample_entries(&root);
+        let archive : Vec<u8> = pack_zip(&entries, &root, false, &ContentOptions::default()).unwrap();
+        write(dir.join("out.zip"), &archive).unwrap();
+        //Only checked where unzip is installed.
+        if let Ok(output) = Command::new("unzip").arg("-l").arg(dir.join("out.zip")).output() {
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            let listing : String = String::from_utf8_lossy(&output.stdout).into_owned();
+            for name in ["a.txt", "sub/b.bin", "empty/"] {
+                assert!(listing.contains(name), "{} is missing from:\n{}", name, listing);
+            }
+            let test = Command::new("unzip").arg("-tq").arg(dir.join("out.zip")).output().unwrap();
+            assert!(test.status.success(), "{}", String::from_utf8_lossy(&test.stdout));
+        }
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn zip_and_tar_gz_round_trip() {
+        let dir : PathBuf = test_dir("round-trip");
+        let root : PathBuf = dir.join("src");
+        let entries : Vec<SourceEntry> = sample_entries(&root);
+        let zip : Vec<u8> = pack_zip(&entries, &root, false, &ContentOptions::default()).unwrap();
+        let tar : Vec<u8> = pack_tar_gz(&entries, &root, false, &ContentOptions::default()).unwrap();
+        for (name, unpacked) in [("zip", unpack_zip(&zip, &dir.join("zip"))), ("tar", unpack_tar_gz(&tar, &dir.join("tar")))] {
+            assert_eq!(unpacked.unwrap().len(), 3);
+            assert_eq!(read_to_string(dir.join(name).join("a.txt")).unwrap(), "first\nsecond\n");
+            assert_eq!(read(dir.join(name).join("sub/b.bin")).unwrap(), [0u8, 159, 146, 150, 255]);
+            assert!(dir.join(name).join("empty").is_dir());
+        }
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn zip_rejects_more_entries_than_fit() {
+        let dir : PathBuf = test_dir("entries");
+        let entries : Vec<SourceEntry> = (0..=u16::MAX as usize).map(|_| SourceEntry::EmptyDir(dir.join("d"))).collect();
+        let error : io::Error = pack_zip(&entries, &dir, false, &ContentOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("number of entries"), "{}", error);
+        assert!(pack_zip(&entries[1..], &dir, false, &ContentOptions::default()).is_ok());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn zip_fields_reject_values_that_would_be_truncated() {
+        assert_eq!(zip_field::<u32>(u32::MAX as usize, "size of", "x").unwrap(), u32::MAX);
+        assert!(zip_field::<u32>(u32::MAX as usize + 1, "size of", "x").is_err());
+        assert!(zip_field::<u16>(u16::MAX as usize + 1, "name length of", "x").is_err());
+    }
+}
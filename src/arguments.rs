@@ -1,11 +1,55 @@
-use std::fs::read_dir;
-use std::path::PathBuf;
+use std::fs::{DirEntry, canonicalize, read_dir, read_link};
+use std::path::{Path, PathBuf};
 
+use crate::boilerplates::ContentOptions;
+use crate::handlers::is_text_file;
 use crate::help_msg::{HelpMessage, help_message};
+use crate::ignore::{BUSBI_IGNORE, IgnoreRules, is_ignored, load_ignore_files, relative_rule_path};
+use crate::manifest::{FileMapping, parse_mapping, parse_mode_override, read_manifest};
+use crate::secrets::{SecretScan, SecretsMode};
+use crate::template::TemplateVars;
 
-pub fn parse_args_advanced(args: &[String]) -> Result<(&str, bool, PathBuf, bool, bool, PathBuf, &str, Vec<PathBuf>, bool), HelpMessage> {
+//Everything the user asked for on the command line, handed over to main() in one piece.
+pub struct Arguments<'a> {
+    pub target_os: &'a str,
+    pub execute: bool,
+    pub x_value: PathBuf,
+    pub d_flag: bool,
+    pub m_flag: bool,
+    pub source_path: PathBuf,
+    pub destination: &'a str,
+    pub list_files: Vec<SourceEntry>,
+    pub close_window: bool,
+    pub mappings: Vec<FileMapping>,
+    pub keep_prefix: bool,
+    pub list_only: bool,
+    pub binary: BinaryMode,
+    pub compress: bool,
+    pub encrypt: bool,
+    pub dedupe: bool,
+    //Manifest of an earlier build, only what changed since then gets delivered.
+    pub since: Option<PathBuf>,
+    //Copy of the source as delivered before, changed text files get patched, see patch.rs.
+    pub patch: Option<PathBuf>,
+    //Name of the install directory on the target, instead of the name of the script.
+    pub install_dir: Option<&'a str>,
+    //Moves files that are already on the target into a backup directory first.
+    pub backup: bool,
+    pub content: ContentOptions,
+    pub secrets: SecretScan,
+}
+
+impl Arguments<'_> {
+    //What the boilerplates take as the destination for paths on the target. The install directory
+    //is named after it, so an update can go into the directory of an earlier build.
+    pub fn install_dest(&self) -> &str {
+        self.install_dir.unwrap_or(self.destination)
+    }
+}
+
+pub fn parse_args_advanced(args: &[String]) -> Result<Arguments<'_>, HelpMessage> {
     
-    let mut iterator_args = args.into_iter();
+    let mut iterator_args = args.iter();
     //First argument is not used, part of rust programming language to return binary path from
     //which it is called.
     let _ = iterator_args.next();
@@ -17,11 +61,34 @@ pub fn parse_args_advanced(args: &[String]) -> Result<(&str, bool, PathBuf, bool
     let mut x_value : PathBuf = PathBuf::new();
     let mut d_flag: bool = false;
     let mut m_flag : bool = false;
-    let mut list_files : Vec<PathBuf> = Vec::new();
+    let mut list_files : Vec<SourceEntry> = Vec::new();
     let mut count : i32 = 0;
     let mut source_path: std::path::PathBuf = PathBuf::new();
     let mut target_dest: &str = "";
+    let mut first_positional: &str = "";
     let mut close_window : bool = false;
+    let mut mappings : Vec<FileMapping> = Vec::new();
+    let mut keep_prefix : bool = false;
+    let mut list_only : bool = false;
+    let mut compress : bool = false;
+    let mut encrypt : bool = false;
+    let mut dedupe : bool = true;
+    let mut since : Option<PathBuf> = None;
+    let mut patch : Option<PathBuf> = None;
+    let mut install_dir : Option<&str> = None;
+    let mut backup : bool = false;
+    let mut content : ContentOptions = ContentOptions::default();
+    let mut secrets : SecretScan = SecretScan::default();
+    let mut walk_options : WalkOptions = WalkOptions {
+        order: WalkOrder::Name,
+        symlinks: SymlinkMode::Link,
+        use_gitignore: false,
+        include: Vec::new(),
+        exclude: Vec::new(),
+        binary: BinaryMode::Skip,
+        max_file_size: Some(DEFAULT_MAX_FILE_SIZE),
+        max_total_size: Some(DEFAULT_MAX_TOTAL_SIZE),
+    };
 
 
     loop {
@@ -47,19 +114,21 @@ pub fn parse_args_advanced(args: &[String]) -> Result<(&str, bool, PathBuf, bool
                 "-d" | "--directory" => match iterator_args.next() {
                             Some(x) => {
                                 d_flag = true;
-                                if m_flag == true {
+                                if m_flag {
                                     return Err(HelpMessage::CannotCombineFlagsMF)
                                 }
                                 source_path = PathBuf::from(x);
+                                content.modes.root = source_path.clone();
                                 target_dest = match iterator_args.next() {
                                     Some(x) => x,
                                     None => return Err(HelpMessage::NoDestinationSpecified)
                                 };
-                                list_files = match parse_directories(list_files, &source_path) {
-                                    Ok(x) => x,
-                                    Err(e) => return Err(e),
-                                };
-                                return Ok((os_target, executable, x_value, d_flag, m_flag, source_path, target_dest, list_files, close_window))
+                                //Links can only be recreated by a Unix shell, Windows gets a copy instead.
+                                if walk_options.symlinks == SymlinkMode::Link && os_target == "windows" {
+                                    walk_options.symlinks = SymlinkMode::Copy;
+                                }
+                                list_files = parse_directories(list_files, &source_path, &walk_options)?;
+                                return Ok(Arguments {target_os: os_target, execute: executable, x_value, d_flag, m_flag, source_path, destination: target_dest, list_files, close_window, mappings, keep_prefix, list_only, binary: walk_options.binary, compress, encrypt, dedupe, since, patch, install_dir, backup, content, secrets})
                                 
                             },
                             None => return Err(HelpMessage::NoValueForFlagD)
@@ -67,55 +136,408 @@ pub fn parse_args_advanced(args: &[String]) -> Result<(&str, bool, PathBuf, bool
                 "-m" | "--many" => match iterator_args.next() {
                             Some(x) => {
                                         m_flag = true;
-                                        if d_flag == true {
+                                        if d_flag {
                                             return Err(HelpMessage::CannotCombineFlagsMF)
                                         }
                                         source_path = PathBuf::from(x);
+                                        content.modes.root = source_path.clone();
                                         target_dest = match iterator_args.next() {
                                             Some(x) => x,
                                             None => return Err(HelpMessage::NoDestinationSpecified)
                                         };
-                                        list_files = match parse_directories(list_files, &source_path) {
-                                            Ok(x) => x,
-                                            Err(e) => return Err(e),
-                                        };
-                                        return Ok((os_target, executable, x_value, d_flag, m_flag, source_path, target_dest, list_files, close_window))
+                                        //Every file gets its own script here, there is no tree to link inside of.
+                                        if walk_options.symlinks == SymlinkMode::Link {
+                                            walk_options.symlinks = SymlinkMode::Copy;
+                                        }
+                                        list_files = parse_directories(list_files, &source_path, &walk_options)?;
+                                        return Ok(Arguments {target_os: os_target, execute: executable, x_value, d_flag, m_flag, source_path, destination: target_dest, list_files, close_window, mappings, keep_prefix, list_only, binary: walk_options.binary, compress, encrypt, dedupe, since, patch, install_dir, backup, content, secrets})
                                         },
                             None => return Err(HelpMessage::NoValueForFlagM),
                         },
+                "--map" => match iterator_args.next() {
+                            Some(x) => mappings.push(parse_mapping(x)?),
+                            None => return Err(HelpMessage::NoValueForFlagMap),
+                        },
+                "--manifest" => match iterator_args.next() {
+                            Some(x) => mappings.append(&mut read_manifest(Path::new(x))?),
+                            None => return Err(HelpMessage::NoValueForFlagManifest),
+                        },
+                "--keep-prefix" => keep_prefix = true,
+                "--gitignore" => walk_options.use_gitignore = true,
+                "--order" => match iterator_args.next().map(|x| x.as_str()) {
+                            Some("name") => walk_options.order = WalkOrder::Name,
+                            Some("dirs-first") => walk_options.order = WalkOrder::DirsFirst,
+                            Some("files-first") => walk_options.order = WalkOrder::FilesFirst,
+                            Some(x) => {println!("Error: '{}' is not a legal option, use 'name', 'dirs-first' or 'files-first'", x); return Err(HelpMessage::WrongArgOrder)},
+                            None => return Err(HelpMessage::NoValueForFlagOrder),
+                        },
+                "--include" => match iterator_args.next() {
+                            Some(x) => walk_options.include.push(x.to_string()),
+                            None => return Err(HelpMessage::NoValueForFlagInclude),
+                        },
+                "--exclude" => match iterator_args.next() {
+                            Some(x) => walk_options.exclude.push(x.to_string()),
+                            None => return Err(HelpMessage::NoValueForFlagExclude),
+                        },
+                "--list" => list_only = true,
+                "--secrets" => match iterator_args.next().map(|x| x.as_str()) {
+                            Some("fail") => secrets.mode = SecretsMode::Fail,
+                            Some("warn") => secrets.mode = SecretsMode::Warn,
+                            Some("off") => secrets.mode = SecretsMode::Off,
+                            Some(x) => {println!("Error: '{}' is not a legal option, use 'fail', 'warn' or 'off'", x); return Err(HelpMessage::WrongArgSecrets)},
+                            None => return Err(HelpMessage::NoValueForFlagSecrets),
+                        },
+                "--secrets-allowlist" => match iterator_args.next() {
+                            Some(x) => secrets.read_allowlist(Path::new(x))?,
+                            None => return Err(HelpMessage::NoValueForFlagAllowlist),
+                        },
+                "-z" | "--compress" => compress = true,
+                "--encrypt" => {encrypt = true; compress = true},
+                "--no-dedupe" => dedupe = false,
+                "--since" => match iterator_args.next() {
+                            Some(x) => since = Some(PathBuf::from(x)),
+                            None => return Err(HelpMessage::NoValueForFlagSince),
+                        },
+                "--patch" => match iterator_args.next() {
+                            Some(x) => patch = Some(PathBuf::from(x)),
+                            None => return Err(HelpMessage::NoValueForFlagPatch),
+                        },
+                "--install-dir" => match iterator_args.next() {
+                            //Everything after the first '.' of a script name is left out for the directory.
+                            Some(x) if x.is_empty() || x.contains('.') => {println!("Error: '{}' is not a valid install directory, it can't be empty or have a '.'.", x); return Err(HelpMessage::InvalidInstallDir)},
+                            Some(x) => install_dir = Some(x),
+                            None => return Err(HelpMessage::NoValueForFlagInstallDir),
+                        },
+                "--backup" => backup = true,
+                "--minify" => content.minify = true,
+                "--mode" => match iterator_args.next() {
+                            Some(x) => content.modes.overrides.push(parse_mode_override(x)?),
+                            None => return Err(HelpMessage::NoValueForFlagMode),
+                        },
+                "--template" => {content.template.get_or_insert_with(TemplateVars::default);},
+                "--var" => match iterator_args.next() {
+                            Some(x) => content.template.get_or_insert_with(TemplateVars::default).set(x)?,
+                            None => return Err(HelpMessage::NoValueForFlagVar),
+                        },
+                "--vars" => match iterator_args.next() {
+                            Some(x) => content.template.get_or_insert_with(TemplateVars::default).read_file(Path::new(x))?,
+                            None => return Err(HelpMessage::NoValueForFlagVars),
+                        },
+                "--binary" => match iterator_args.next().map(|x| x.as_str()) {
+                            Some("skip") => walk_options.binary = BinaryMode::Skip,
+                            Some("encode") => walk_options.binary = BinaryMode::Encode,
+                            Some("fail") => walk_options.binary = BinaryMode::Fail,
+                            Some(x) => {println!("Error: '{}' is not a legal option, use 'skip', 'encode' or 'fail'", x); return Err(HelpMessage::WrongArgBinary)},
+                            None => return Err(HelpMessage::NoValueForFlagBinary),
+                        },
+                "--max-file-size" => match iterator_args.next() {
+                            Some(x) => walk_options.max_file_size = parse_size(x)?,
+                            None => return Err(HelpMessage::NoValueForFlagMaxSize),
+                        },
+                "--max-total-size" => match iterator_args.next() {
+                            Some(x) => walk_options.max_total_size = parse_size(x)?,
+                            None => return Err(HelpMessage::NoValueForFlagMaxSize),
+                        },
+                "--symlinks" => match iterator_args.next().map(|x| x.as_str()) {
+                            Some("link") => walk_options.symlinks = SymlinkMode::Link,
+                            Some("copy") => walk_options.symlinks = SymlinkMode::Copy,
+                            Some("skip") => walk_options.symlinks = SymlinkMode::Skip,
+                            Some(x) => {println!("Error: '{}' is not a legal option, use 'link', 'copy' or 'skip'", x); return Err(HelpMessage::WrongArgSymlinks)},
+                            None => return Err(HelpMessage::NoValueForFlagSymlinks),
+                        },
                 "-c" | "--close" => close_window = true, 
                 "-h" | "--help" => {
                                         help_message();
                                         return Err(HelpMessage::PrintingHelp)
                                     },
-                s => {if count > 0 {target_dest = s} else {count += 1; source_path = PathBuf::from(s)}}, 
+                s => {if count > 0 {target_dest = s} else {count += 1; first_positional = s; source_path = PathBuf::from(s)}}, 
             },
             None => break,
         }
     }
+    //With mappings every source already comes with its own target, so the only positional
+    //argument left is the name of the bad USB script itself.
+    if !mappings.is_empty() {
+        if target_dest.is_empty() {
+            target_dest = first_positional;
+        }
+        if target_dest.is_empty() {
+            return Err(HelpMessage::NoDestinationSpecified)
+        }
+        return Ok(Arguments {target_os: os_target, execute: executable, x_value, d_flag, m_flag, source_path, destination: target_dest, list_files, close_window, mappings, keep_prefix, list_only, binary: walk_options.binary, compress, encrypt, dedupe, since, patch, install_dir, backup, content, secrets})
+    }
     if args.len() < 3 {
         return Err(HelpMessage::NotEnoughArgs)
     }
-    Ok((os_target, executable, x_value, d_flag, m_flag, source_path, target_dest, list_files, close_window))
+    content.modes.root = source_path.clone();
+    Ok(Arguments {target_os: os_target, execute: executable, x_value, d_flag, m_flag, source_path, destination: target_dest, list_files, close_window, mappings, keep_prefix, list_only, binary: walk_options.binary, compress, encrypt, dedupe, since, patch, install_dir, backup, content, secrets})
 }
 
-//Function to complete the list of files within a directory.
-pub fn parse_directories(mut list : Vec<PathBuf>, source_path: &PathBuf) -> Result<Vec<PathBuf>, HelpMessage> {
+//What to do with symlinks found inside a '-d' or '-m' directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymlinkMode {
+    //Recreate the link itself on the target. Only possible on Unix targets.
+    Link,
+    //Follow the link and copy whatever it points to.
+    Copy,
+    Skip,
+}
+
+//In which order the entries of each directory are walked. Always sorted by name so building the
+//same directory twice gives exactly the same script, whatever order the file system lists them in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WalkOrder {
+    Name,
+    DirsFirst,
+    FilesFirst,
+}
 
-    let mut directory_iterator = match read_dir(source_path) {
+//What to do with files that aren't text, typing them line by line would cut them off at the first
+//byte that isn't valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryMode {
+    //Leave them out and mention them in the report.
+    Skip,
+    //Type them as base64 and decode them on the target.
+    Encode,
+    //Stop without creating anything.
+    Fail,
+}
+
+//Typing 1MB takes the Flipper a good while already, anything bigger is most likely a mistake.
+pub const DEFAULT_MAX_FILE_SIZE : u64 = 1024 * 1024;
+pub const DEFAULT_MAX_TOTAL_SIZE : u64 = 10 * 1024 * 1024;
+
+//Settings that decide which entries the walk over a source directory returns.
+pub struct WalkOptions {
+    pub order: WalkOrder,
+    pub symlinks: SymlinkMode,
+    //Also honour .gitignore files, .busbiignore files are always read.
+    pub use_gitignore: bool,
+    //Globs from '--include', if there are any only files matching one of them are kept.
+    pub include: Vec<String>,
+    //Globs from '--exclude'.
+    pub exclude: Vec<String>,
+    pub binary: BinaryMode,
+    //Files bigger than this are left out, None means no limit.
+    pub max_file_size: Option<u64>,
+    //Once the files found add up to this, the rest is left out. None means no limit.
+    pub max_total_size: Option<u64>,
+}
+
+//Everything found while walking a source directory that has to be recreated on the target.
+#[derive(Debug, Clone)]
+pub enum SourceEntry {
+    File(PathBuf),
+    //A file that isn't text and gets delivered base64 encoded.
+    Binary(PathBuf),
+    EmptyDir(PathBuf),
+    //The path of the link and the path it points to, exactly as stored in the link.
+    Symlink(PathBuf, PathBuf),
+}
+
+//What the walk keeps track of while it goes down the directory tree.
+struct WalkState<'a> {
+    options: &'a WalkOptions,
+    root: PathBuf,
+    //Canonical paths of the directories currently being walked, to find symlink loops.
+    ancestors: Vec<PathBuf>,
+    //Ignore files of the directories currently being walked, outermost first.
+    rule_sets: Vec<IgnoreRules>,
+    include: IgnoreRules,
+    exclude: IgnoreRules,
+    total_size: u64,
+    //Files left out because of their size or content, with the reason why.
+    excluded: Vec<(PathBuf, String)>,
+}
+
+impl WalkState<'_> {
+    fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let relative_path : String = relative_rule_path(path, &self.root);
+        if is_ignored(&self.rule_sets, &relative_path, is_dir) || self.exclude.decide(&relative_path, is_dir) == Some(true) {
+            return true
+        }
+        !is_dir && !self.include.is_empty() && self.include.decide(&relative_path, is_dir) != Some(true)
+    }
+
+    //Checks the size and content of a file that made it past the ignore rules. Gives the entry to
+    //deliver it with, or None when it has to be left out.
+    fn check_file(&mut self, path: PathBuf) -> Option<SourceEntry> {
+        let size : u64 = match path.metadata() {
+            Ok(x) => x.len(),
+            Err(e) => {self.excluded.push((path, format!("could not read it: {}", e))); return None},
+        };
+        if let Some(max) = self.options.max_file_size && size > max {
+            self.excluded.push((path, format!("{} bytes is over the file size limit of {} bytes", size, max)));
+            return None
+        }
+        if let Some(max) = self.options.max_total_size && self.total_size + size > max {
+            self.excluded.push((path, format!("would go over the total size limit of {} bytes", max)));
+            return None
+        }
+        let is_text : bool = match is_text_file(&path) {
+            Ok(x) => x,
+            Err(e) => {self.excluded.push((path, format!("could not read it: {}", e))); return None},
+        };
+        if !is_text {
+            match self.options.binary {
+                BinaryMode::Skip | BinaryMode::Fail => {
+                    self.excluded.push((path, "not a text file".to_string()));
+                    return None
+                },
+                BinaryMode::Encode => {
+                    println!("Warning: {} is not a text file, it will be delivered base64 encoded.", path.display());
+                    self.total_size += size;
+                    return Some(SourceEntry::Binary(path))
+                },
+            }
+        }
+        self.total_size += size;
+        Some(SourceEntry::File(path))
+    }
+}
+
+//Function to complete the list of files within a directory. Walks all sub-directories so the list
+//comes back flat, leaving out whatever the ignore files and '--include'/'--exclude' say. Directories
+//reached through a symlink are checked against the directories currently being walked, so a link
+//pointing back up the tree can't make this loop forever.
+pub fn parse_directories(list : Vec<SourceEntry>, source_path: &Path, options: &WalkOptions) -> Result<Vec<SourceEntry>, HelpMessage> {
+    let mut state = WalkState {
+        options,
+        root: source_path.to_path_buf(),
+        ancestors: Vec::new(),
+        rule_sets: Vec::new(),
+        include: IgnoreRules::new("", &options.include),
+        exclude: IgnoreRules::new("", &options.exclude),
+        total_size: 0,
+        excluded: Vec::new(),
+    };
+    if options.use_gitignore {
+        //Git never looks inside its own directory, so neither should we.
+        state.rule_sets.push(IgnoreRules::new("", &[".git/".to_string()]));
+    }
+    let list : Vec<SourceEntry> = walk_directory(list, source_path, &mut state)?;
+    if !state.excluded.is_empty() {
+        println!("Left out {} file(s):", state.excluded.len());
+        for (path, reason) in &state.excluded {
+            println!("  {}: {}", path.display(), reason);
+        }
+        if options.binary == BinaryMode::Fail && state.excluded.iter().any(|x| x.1 == "not a text file") {
+            println!("Error: found files that are not text, use '--binary skip' or '--binary encode'.");
+            return Err(HelpMessage::BinaryFile)
+        }
+    }
+    Ok(list)
+}
+
+fn walk_directory(mut list : Vec<SourceEntry>, source_path: &Path, state: &mut WalkState) -> Result<Vec<SourceEntry>, HelpMessage> {
+
+    let directory_iterator = match read_dir(source_path) {
         Ok(x) => x,
         Err(e) => {println!("Argument provided for '-d' is not valid, got this error: {:?}", e); return Err(HelpMessage::WrongDirectoryArg)},
     };
-    loop {
-        let current_entry = directory_iterator.next();
+    let mut entries : Vec<DirEntry> = Vec::new();
+    for current_entry in directory_iterator {
         match current_entry {
-            Some(x) => match x {
-                Ok(y) => list.push(y.path()),
-                Err(e) => {eprintln!("got error: {:?}", e); return Err(HelpMessage::FailedToGetFile)},
-            },
-            None => break,
+            Ok(y) => entries.push(y),
+            Err(e) => {eprintln!("got error: {:?}", e); return Err(HelpMessage::FailedToGetFile)},
+        }
+    }
+    let follows_links : bool = state.options.symlinks == SymlinkMode::Copy;
+    let is_dir_entry = |entry: &DirEntry| -> bool {
+        match entry.file_type() {
+            Ok(x) if x.is_symlink() => follows_links && entry.path().is_dir(),
+            Ok(x) => x.is_dir(),
+            Err(_e) => false,
+        }
+    };
+    match state.options.order {
+        WalkOrder::Name => entries.sort_by_key(|x| x.file_name()),
+        WalkOrder::DirsFirst => entries.sort_by_key(|x| (!is_dir_entry(x), x.file_name())),
+        WalkOrder::FilesFirst => entries.sort_by_key(|x| (is_dir_entry(x), x.file_name())),
+    }
+    let canonical_path : PathBuf = match canonicalize(source_path) {
+        Ok(x) => x,
+        Err(e) => {eprintln!("got error: {:?}", e); return Err(HelpMessage::FailedToGetFile)},
+    };
+    state.ancestors.push(canonical_path);
+    let mut ignore_files : Vec<IgnoreRules> = load_ignore_files(source_path, &state.root, state.options.use_gitignore);
+    let ignore_file_count : usize = ignore_files.len();
+    state.rule_sets.append(&mut ignore_files);
+    let mut is_empty : bool = true;
+    for entry in entries {
+        is_empty = false;
+        let entry_path : PathBuf = entry.path();
+        if entry.file_name() == BUSBI_IGNORE {
+            continue;
+        }
+        //The file type of a directory entry doesn't follow symlinks, unlike Path::is_dir().
+        let file_type = match entry.file_type() {
+            Ok(x) => x,
+            Err(e) => {eprintln!("got error: {:?}", e); return Err(HelpMessage::FailedToGetFile)},
+        };
+        if file_type.is_symlink() {
+            match state.options.symlinks {
+                SymlinkMode::Skip => println!("Skipping symlink {}", entry_path.display()),
+                SymlinkMode::Link => match read_link(&entry_path) {
+                    Ok(_x) if state.is_excluded(&entry_path, false) => continue,
+                    Ok(x) => list.push(SourceEntry::Symlink(entry_path, x)),
+                    Err(e) => {eprintln!("got error: {:?}", e); return Err(HelpMessage::FailedToGetFile)},
+                },
+                SymlinkMode::Copy => match canonicalize(&entry_path) {
+                    Ok(x) if state.is_excluded(&entry_path, x.is_dir()) => continue,
+                    Ok(x) if state.ancestors.contains(&x) => eprintln!("Symlink loop: {} points back to {}, skipping it.", entry_path.display(), x.display()),
+                    Ok(x) if x.is_dir() => list = walk_directory(list, &entry_path, state)?,
+                    Ok(_x) => list.extend(state.check_file(entry_path)),
+                    Err(_e) => eprintln!("Symlink {} points to nothing, skipping it.", entry_path.display()),
+                },
+            }
+        } else if state.is_excluded(&entry_path, file_type.is_dir()) {
+            continue;
+        } else if file_type.is_dir() {
+            list = walk_directory(list, &entry_path, state)?;
+        } else {
+            list.extend(state.check_file(entry_path));
         }
     }
+    state.ancestors.pop();
+    let kept_rule_sets : usize = state.rule_sets.len() - ignore_file_count;
+    state.rule_sets.truncate(kept_rule_sets);
+    if is_empty && !state.ancestors.is_empty() {
+        list.push(SourceEntry::EmptyDir(source_path.to_path_buf()));
+    }
     Ok(list)
 
 }
+
+//Reads a size like '512', '100K', '2M' or '1G'. 'none' turns the limit off.
+fn parse_size(value: &str) -> Result<Option<u64>, HelpMessage> {
+    if value == "none" {
+        return Ok(None)
+    }
+    let upper : String = value.to_uppercase();
+    let number : &str = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, factor) : (&str, u64) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1024),
+        Some('M') => (&number[..number.len() - 1], 1024 * 1024),
+        Some('G') => (&number[..number.len() - 1], 1024 * 1024 * 1024),
+        _ => (number, 1),
+    };
+    match number.parse::<u64>().ok().and_then(|x| x.checked_mul(factor)) {
+        Some(x) => Ok(Some(x)),
+        None => {println!("Error: '{}' is not a valid size, use something like '512', '100K' or '2M'.", value); Err(HelpMessage::WrongArgSize)},
+    }
+}
+
+//Prints every entry the bad USB script(s) will recreate, relative to the source directory.
+pub fn print_file_list(list : &[SourceEntry], source_path: &Path) {
+    for entry in list {
+        match entry {
+            SourceEntry::File(x) => println!("file  {}", relative_rule_path(x, source_path)),
+            SourceEntry::Binary(x) => println!("bin   {}", relative_rule_path(x, source_path)),
+            SourceEntry::EmptyDir(x) => println!("dir   {}/", relative_rule_path(x, source_path)),
+            SourceEntry::Symlink(x, y) => println!("link  {} -> {}", relative_rule_path(x, source_path), y.display()),
+        }
+    }
+    println!("{} entries.", list.len());
+}
@@ -1,22 +1,39 @@
-use std::path::PathBuf;
+use std::fs::read;
+use std::path::{Path, PathBuf};
 
+use crate::crypto::PBKDF2_ITERATIONS;
+use crate::ducky::{ScriptBuilder, parse_line};
+use crate::encoding::base64_lines;
 use crate::help_msg::HelpMessage;
-use crate::handlers::{adapt_path, read_lines};
-
-pub fn executable_boilerplate(os_type: &str, source_file: &PathBuf, destination : &str, d_flag: bool, m_flag: bool) -> Result<String, HelpMessage> {
-    let mut execute_string : String = String::new();
+use crate::handlers::{adapt_path, changed_file_mode, is_text_file, read_lines, resolve_target_path};
+use crate::manifest::ModeOverrides;
+use crate::metadata::Metadata;
+use crate::minify::minify_lines;
+use crate::patch::POWERSHELL_PATCH_FUNCTION;
+use crate::quoting::{quote_path, quote_powershell, quote_unix_word};
+use crate::template::{TemplateVars, collect_prompts, prompt_marker, render_line};
+
+//What gets done to the content of text files before it is typed on the target, and the
+//permissions delivered files get.
+#[derive(Debug, Clone, Default)]
+pub struct ContentOptions {
+    //Leaves out full-line comments and blank lines, see minify.rs.
+    pub minify: bool,
+    //Fills in '{{ name }}' placeholders when set, see template.rs.
+    pub template: Option<TemplateVars>,
+    //The '--mode' overrides, see manifest.rs.
+    pub modes: ModeOverrides,
+}
 
+pub fn executable_boilerplate(os_type: &str, source_file: &Path, destination : &str, d_flag: bool, m_flag: bool) -> Result<String, HelpMessage> {
     let mod_dest : Vec<&str> = destination.split('.').collect();
     let no_suffix_dest : &str = mod_dest[0];
 
-    let file_name : &str = match &source_file.file_name() {
+    let file_name : &str = match source_file.file_name() {
         Some(x) => x.to_str().unwrap(),
         None => panic!("No file name. Unrecoverable error."),
     };
-    let adapted_path : PathBuf = match adapt_path(&source_file, os_type) {
-        Ok(x) => x,
-        Err(e) => return Err(e),
-    };
+    let adapted_path : PathBuf = adapt_path(source_file, os_type)?;
     let mut no_prefix_adapted_path : &str = "";
     if os_type.to_lowercase() == "windows" {
         no_prefix_adapted_path = match adapted_path.strip_prefix("\\") {
@@ -32,85 +49,124 @@ pub fn executable_boilerplate(os_type: &str, source_file: &PathBuf, destination
 
     }
 
-    if os_type.to_lowercase() == "windows" {
-        execute_string.push_str(
-                "DELAY 100\n\
-                STRINGLN Set-ExecutionPolicy RemoteSigned -Scope CurrentUser\n\
-                DELAY 200\n\
-                ");
+    let run_path : String = if d_flag {
+        home_path(os_type, &[no_suffix_dest, no_prefix_adapted_path])
+    } else if m_flag {
+        home_path(os_type, &[no_suffix_dest, file_name])
+    } else {
+        //Note that even in this standard case where we just generate 1 file, we still need to
+        //specify the name of the file we want to execute as that is the name of the file that will
+        //be added to the execution busbi script. 
+        home_path(os_type, &[file_name])
+    };
+    //The uninstall script of a '-d' directory puts the execution policy back, see uninstall.rs.
+    let mut script : String = String::new();
+    if d_flag && os_type.to_lowercase() == "windows" {
+        let mut save : ScriptBuilder = ScriptBuilder::new();
+        save.string_ln(&format!("$busbi_policy = {}", quote_path(os_type, &policy_file(os_type, destination))))
+            .string_ln(POWERSHELL_SAVE_POLICY);
+        script.push_str(&save.to_string());
+    }
+    script.push_str(&run_path_boilerplate(os_type, &run_path)?);
+    Ok(script)
+}
 
-    } 
-    if d_flag {
-        if os_type.to_lowercase() == "windows" {
-            execute_string.push_str(format!(
-                    "STRINGLN $code = Get-Content $HOME\\{}\\{} -Raw\n\
-                    DELAY 400\n\
-                    STRINGLN Invoke-Expression $code\n\
-                    ", no_suffix_dest, no_prefix_adapted_path).as_str());
+//Where a Windows target keeps its execution policy from before busbi changed it, only the first
+//time so running the script again doesn't overwrite it.
+fn policy_file(os_type: &str, dest: &str) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+    home_path(os_type, &[BACKUP_DIR, &format!("{}-execution-policy.txt", no_suffix_dest)])
+}
 
-        } else if os_type.to_lowercase() == "unix" {
-            execute_string.push_str(format!(
-                    "STRINGLN chmod +x $HOME/{}/{}\n\
-                    DELAY 100\n\
-                    STRINGLN $HOME/{}/{}\n\
-                    ",no_suffix_dest, no_prefix_adapted_path, no_suffix_dest , no_prefix_adapted_path).as_str());
+//Sets the execution policy back to what it was before the '-d' script with '-x' ran.
+pub fn restore_policy_boilerplate(os_type: &str, dest: &str) -> String {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln(&format!("$busbi_policy = {}", quote_path(os_type, &policy_file(os_type, dest))))
+            .string_ln(POWERSHELL_RESTORE_POLICY);
+    }
+    script.to_string()
+}
 
-        } else {
-            return Err(HelpMessage::WrongArgOS)
-        }
+//Executes a file that was written to a mapped target path.
+pub fn execute_target_boilerplate(os_type: &str, target: &str) -> Result<String, HelpMessage> {
+    run_path_boilerplate(os_type, &resolve_target_path(os_type, target))
+}
 
-    } else if m_flag {
-        if os_type.to_lowercase() == "windows" {
-            execute_string.push_str(format!(
-                    "STRINGLN $code = Get-Content $HOME\\{}\\{} -Raw\n\
-                    DELAY 400\n\
-                    STRINGLN Invoke-Expression $code\n\
-                    ", no_suffix_dest, file_name).as_str());
+fn run_path_boilerplate(os_type: &str, run_path: &str) -> Result<String, HelpMessage> {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    let quoted_path : String = quote_path(os_type, run_path);
 
-        } else if os_type.to_lowercase() == "unix" {
-            execute_string.push_str(format!(
-                    "STRINGLN chmod +x $HOME/{}/{}\n\
-                    DELAY 100\n\
-                    STRINGLN $HOME/{}/{}\n\
-                    ", no_suffix_dest, file_name, no_suffix_dest, file_name).as_str());
+    if os_type.to_lowercase() == "windows" {
+        script.delay(100)
+            .string_ln("Set-ExecutionPolicy RemoteSigned -Scope CurrentUser")
+            .delay(200)
+            .string_ln(&format!("$code = Get-Content -LiteralPath {} -Raw", quoted_path))
+            .delay(400)
+            .string_ln("Invoke-Expression $code");
 
-        } else {
-            return Err(HelpMessage::WrongArgOS)
-        }
+    } else if os_type.to_lowercase() == "unix" {
+        script.string_ln(&format!("chmod +x {}", quoted_path))
+            .delay(100)
+            .string_ln(&quoted_path);
 
-        //Note that even in the below standard case where we just generate 1 file, we still need to
-        //specify the name of the file we want to execute as that is the name of the file that will
-        //be added to the execution busbi script. 
     } else {
-        if os_type.to_lowercase() == "windows" {
-            execute_string.push_str(format!(
-                    "STRINGLN $code = Get-Content $HOME\\{} -Raw\n\
-                    DELAY 400\n\
-                    STRINGLN Invoke-Expression $code\n\
-                    ",file_name).as_str());
-
-        } else if os_type.to_lowercase() == "unix" {
-            execute_string.push_str(format!(
-                    "STRINGLN chmod +x $HOME/{}\n\
-                    DELAY 100\n\
-                    STRINGLN $HOME/{}\n\
-                    ", file_name, file_name).as_str());
+        return Err(HelpMessage::WrongArgOS)
+    }
+    Ok(script.to_string())
+}
 
-        } else {
-            return Err(HelpMessage::WrongArgOS)
+//Builds a path below $HOME on the target out of the given parts, using the separator of the target
+//OS. Empty parts are skipped.
+fn home_path(os_type: &str, parts: &[&str]) -> String {
+    let separator : &str = if os_type.to_lowercase() == "windows" {"\\"} else {"/"};
+    let mut path : String = String::from("$HOME");
+    for part in parts {
+        let part : &str = part.trim_matches(['/', '\\']);
+        if !part.is_empty() {
+            path.push_str(separator);
+            path.push_str(part);
         }
+    }
+    path
+}
 
+//Where make_file_boilerplate() writes a file on the target, unquoted.
+pub fn target_file_path(os_type: &str, source_file: &Path, install_path: &Path, dest: &str, d_flag: bool, m_flag: bool) -> String {
+    let mod_path : PathBuf = match adapt_path(install_path, os_type) {
+        Ok(x) => x,
+        Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
+    };
+    let file_name : &str = match source_file.file_name() {
+        Some(x) => x.to_str().unwrap(),
+        None => panic!("No file name. Unrecoverable error."),
+    };
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+    if d_flag {
+        home_path(os_type, &[no_suffix_dest, &mod_path.to_string_lossy()])
+    } else if m_flag {
+        home_path(os_type, &[no_suffix_dest, file_name])
+    } else {
+        //This is the normal base case where the user doesn't do multi file creation, the file
+        //goes straight into $HOME.
+        home_path(os_type, &[file_name])
     }
-    Ok(execute_string)
 }
 
-pub fn make_file_boilerplate(os_type: &str, source_file: &PathBuf, dest: &str, d_flag: bool, m_flag: bool) -> String {
-    let mut mf_string: String = String::new();
+//'install_path' is where the file goes below the install directory on the target, see
+//install_relative_path(). It is only used with '-d'. Files that aren't text are typed as base64
+//and decoded on the target.
+pub fn make_file_boilerplate(os_type: &str, source_file: &Path, install_path: &Path, dest: &str, d_flag: bool, m_flag: bool, content: &ContentOptions) -> String {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    let encode : bool = !is_text_file(source_file).unwrap_or(true);
+    let prompts : Vec<String> = file_prompts(source_file, content);
     // Can only call .parent() on a PathBuf that is valid for the current OS (on which the command
     // runs). It doesn't work if i modify the path to the target OS first and then try to call
     // .parent() on it. That's why I do this before here, to create 2 seperate PathBuf's, one for
     // creating directories and the other for making the file itself.  
-    let path_parent : PathBuf = match &source_file.parent() {
+    let path_parent : PathBuf = match install_path.parent() {
         Some(x) => PathBuf::from(x),
         None => panic!("Unrecoverable, failed to get parent path."),
     };
@@ -118,138 +174,638 @@ pub fn make_file_boilerplate(os_type: &str, source_file: &PathBuf, dest: &str, d
         Ok(x) => x,
         Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
     };   
-    let mod_path : PathBuf = match adapt_path(&source_file, os_type) {
-        Ok(x) => x,
-        Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
-    };
-    let file_name : &str = match &source_file.file_name() {
-        Some(x) => x.to_str().unwrap(),
-        None => panic!("No file name. Unrecoverable error."),
-    };
     // To get rid of the file extensions as it looks weird to call a directory 'script.txt'.
     let mod_dest : Vec<&str> = dest.split('.').collect();
     let no_suffix_dest : &str = mod_dest[0];
 
+    let dir_path : String = home_path(os_type, &[no_suffix_dest, &mod_path_parent.to_string_lossy()]);
+    let file_path : String = target_file_path(os_type, source_file, install_path, dest, d_flag, m_flag);
+
     if os_type.to_lowercase() == "windows" {
         if d_flag {
-            mf_string.push_str(format!(
-                "STRINGLN New-Item -ItemType Directory -Path \"$HOME\\{}\\{}\" -Force\n\
-                " , no_suffix_dest, mod_path_parent.display()).as_str())
+            script.string_ln(&format!("New-Item -ItemType Directory -Path {} -Force", quote_path(os_type, &dir_path)));
         }
-        mf_string.push_str(
-        "STRINGLN $file = @'\n\
-        ");
+        script.string_ln("$file = @'");
     } else if os_type.to_lowercase() == "unix" {
         if d_flag {
-            mf_string.push_str(format!(
-                "STRINGLN mkdir -p $HOME/{}/{}\n\
-                STRINGLN cat > $HOME/{}/{}\n\
-                " ,no_suffix_dest, mod_path_parent.display(), 
-                &no_suffix_dest, mod_path.display()
-                ).as_str());
-
-        } else if m_flag {
-            mf_string.push_str(format!(
-                "STRINGLN cat > $HOME/{}/{}\n\
-                ", &no_suffix_dest, file_name).as_str());
+            script.string_ln(&format!("mkdir -p {}", quote_path(os_type, &dir_path)));
+        }
+        if !encode && !prompts.is_empty() {
+            script.string_ln(PROMPT_FILE_START);
         } else {
-            //This should use regular 'dest' as it's the normal base case where the user doesn't do
-            //multi file creation. 
-            mf_string.push_str(format!(
-                "STRINGLN cat > $HOME/{}\n\
-                ", file_name).as_str());
+            let write_command : &str = if encode {"base64 -d"} else {"cat"};
+            script.string_ln(&format!("{} > {}", write_command, quote_path(os_type, &file_path)));
+        }
+    }
+    if encode {
+        add_encoded_lines(&mut script, source_file);
+    } else {
+        add_file_lines(&mut script, source_file, content);
+    }
+    if os_type.to_lowercase() == "windows" && encode {
+        script.string_ln("'@")
+            .string_ln(&format!("[IO.File]::WriteAllBytes({}, [Convert]::FromBase64String($file))", quote_path(os_type, &file_path)));
+    } else if os_type.to_lowercase() == "windows" {
+        script.string_ln("'@");
+        add_filled_prompts(&mut script, os_type, &prompts, &file_path);
+        script.string_ln(&format!("Set-Content -LiteralPath {} -Value $file", quote_path(os_type, &file_path)));
+    } else if os_type.to_lowercase() == "unix" {
+        script.key("CTRL d");
+        if !encode {
+            add_filled_prompts(&mut script, os_type, &prompts, &file_path);
+        }
+        add_permissions(&mut script, os_type, &file_path, content.modes.chmod_mode(source_file));
+    }
+    script.to_string()
+}
+
+//Creates a directory that is empty in the source tree, all other directories get created on the
+//way to the files they contain.
+pub fn empty_dir_boilerplate(os_type: &str, install_path: &Path, dest: &str) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+    let mod_path : PathBuf = match adapt_path(install_path, os_type) {
+        Ok(x) => x,
+        Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
+    };
+    let dir_path : String = home_path(os_type, &[no_suffix_dest, &mod_path.to_string_lossy()]);
 
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln(&format!("New-Item -ItemType Directory -Path {} -Force", quote_path(os_type, &dir_path)));
+    } else {
+        script.string_ln(&format!("mkdir -p {}", quote_path(os_type, &dir_path)));
+    }
+    script.to_string()
+}
+
+//Deletes a file that was delivered by an earlier build but is gone from the source, see '--since'.
+//Doesn't complain if it was already removed on the target.
+pub fn removed_file_boilerplate(os_type: &str, install_path: &Path, dest: &str) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+    let mod_path : PathBuf = match adapt_path(install_path, os_type) {
+        Ok(x) => x,
+        Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
+    };
+    let file_path : String = home_path(os_type, &[no_suffix_dest, &mod_path.to_string_lossy()]);
+
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln(&format!("Remove-Item -LiteralPath {} -Force -ErrorAction SilentlyContinue", quote_path(os_type, &file_path)));
+    } else {
+        script.string_ln(&format!("rm -f {}", quote_path(os_type, &file_path)));
+    }
+    script.to_string()
+}
+
+//Patches a file that is already in the install directory with a diff from unified_diff(), see
+//patch.rs. Windows targets need busbi_patch defined once, with 'define_function' on the first patch.
+//A file on the target that isn't the version the diff was made for is left as it is, with an
+//error in the terminal.
+pub fn patch_file_boilerplate(os_type: &str, install_path: &Path, dest: &str, diff: &[String], mode: Option<u32>, define_function: bool) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+    let mod_path : PathBuf = match adapt_path(install_path, os_type) {
+        Ok(x) => x,
+        Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
+    };
+    let target_path : String = home_path(os_type, &[no_suffix_dest, &mod_path.to_string_lossy()]);
+    let file_path : String = quote_path(os_type, &target_path);
+
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if os_type.to_lowercase() == "windows" {
+        if define_function {
+            script.string_lines(POWERSHELL_PATCH_FUNCTION);
         }
+        script.string_ln("$file = @'")
+            .append(typed_lines(diff))
+            .string_ln("'@")
+            .string_ln(&format!("busbi_patch {} $file", file_path));
+    } else {
+        //Checked with '--dry-run' first, a patch that doesn't fit changes nothing. '-f' keeps patch
+        //from asking anything, the answer would be taken from the lines typed after it.
+        let patch_path : String = quote_path(os_type, &home_path(os_type, &[no_suffix_dest, &format!("{}{}", mod_path.to_string_lossy(), PATCH_SUFFIX)]));
+        script.string_ln(&format!("cat > {}", patch_path))
+            .append(typed_lines(diff))
+            .key("CTRL d")
+            .string_ln(&format!("patch --dry-run -f -s {} {} > /dev/null && patch -f -s {} {} || echo \"busbi: could not patch, it is not the version the patch was made for:\" {} >&2", file_path, patch_path, file_path, patch_path, file_path))
+            .string_ln(&format!("rm -f {}", patch_path));
+        add_permissions(&mut script, os_type, &target_path, mode);
     }
-    if let Ok(lines) = read_lines(&source_file) {
-        for line in lines.map_while(Result::ok) {
-            let mut mod_line : String = line.clone();
-            // Its necessary to check if the line is empty. The ducky script runs so fast that
-            // especially on windows a STRINGLN with nothing after will be printed out as
-            // "TRINGLN", without the "S" at the start, which powershell just loses for some
-            // reason. this check is to mitigate that and try to write the lines correctly.
-            match line.trim().is_empty() {
-                true => {
-                    mod_line.push_str("ENTER\n");
-                    mf_string.push_str(mod_line.as_str());
-                },
-                false => {
-                    //Changes to the line applied here before being written to the buffer.
-                    mod_line.insert_str(0, "STRINGLN ");
-                    mod_line.push_str("\n");
-                    mf_string.push_str(mod_line.as_str());
-                },
-            }
+    script.to_string()
+}
 
+//Moves the files that are already on the target out of the way before anything is written, into a
+//backup directory named after the script and the time it ran. 'targets' are paths like
+//target_file_path() gives them, 'true' for files that get patched in place and are copied instead.
+pub fn backup_boilerplate(os_type: &str, dest: &str, targets: &[(String, bool)]) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if targets.is_empty() {
+        return script.to_string()
+    }
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln(&format!("$busbi_backup = {} + (Get-Date -Format '-yyyyMMdd-HHmmss')", quote_path(os_type, &home_path(os_type, &[BACKUP_DIR, no_suffix_dest]))))
+            .string_lines(POWERSHELL_BACKUP_FUNCTION);
+        for (target, copy) in targets {
+            let copy : &str = if *copy {" $true"} else {""};
+            script.string_ln(&format!("busbi_backup_file {} {}{}", quote_path(os_type, target), quote_powershell(&backup_name(target)), copy));
         }
     } else {
-        println!("Err: File you specified doesn't exist or something else went wrong. Your file: {}", &source_file.display());
-        panic!("Stopped due to above error.")
+        script.string_ln(&format!("busbi_backup={}-$(date +%Y%m%d-%H%M%S)", quote_path(os_type, &home_path(os_type, &[BACKUP_DIR, no_suffix_dest]))))
+            .string_lines(UNIX_BACKUP_FUNCTION);
+        for (target, copy) in targets {
+            let copy : &str = if *copy {" copy"} else {""};
+            script.string_ln(&format!("busbi_backup_file {} {}{}", quote_path(os_type, target), quote_unix_word(&backup_name(target)), copy));
+        }
+    }
+    script.to_string()
+}
+
+//Where a target file goes inside the backup directory, its path below $HOME or below the root.
+fn backup_name(target: &str) -> String {
+    let name : &str = target.strip_prefix("$HOME").unwrap_or(target);
+    let name : &str = match name.split_once(':') {
+        Some((drive, rest)) if drive.len() == 1 => rest,
+        _ => name,
+    };
+    name.trim_start_matches(['/', '\\']).to_string()
+}
+
+//Moves files back from the backup directories of earlier runs of the script, the oldest backup
+//of each file first as that is the one from before busbi. 'targets' are like for backup_boilerplate().
+pub fn restore_boilerplate(os_type: &str, dest: &str, targets: &[String]) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if targets.is_empty() {
+        return script.to_string()
     }
     if os_type.to_lowercase() == "windows" {
-        if m_flag {
-            mf_string.push_str(format!(
-                "STRINGLN '@\n\
-                STRINGLN Set-Content -Path $HOME\\{}\\{} -Value $file\n\
-                ", &no_suffix_dest, file_name).as_str());
-
-        } else if d_flag {
-            mf_string.push_str(format!(
-                "STRINGLN '@\n\
-                STRINGLN Set-Content -Path $HOME\\{}\\{} -Value $file\n\
-                ", &no_suffix_dest, mod_path.display()).as_str());
+        script.string_ln(&format!("$busbi_backup_name = {}", quote_powershell(no_suffix_dest)))
+            .string_lines(POWERSHELL_RESTORE_FUNCTION);
+        for target in targets {
+            script.string_ln(&format!("busbi_restore_file {} {}", quote_path(os_type, target), quote_powershell(&backup_name(target))));
+        }
+    } else {
+        script.string_ln(&format!("busbi_backup_name={}", quote_unix_word(no_suffix_dest)))
+            .string_lines(UNIX_RESTORE_FUNCTION);
+        for target in targets {
+            script.string_ln(&format!("busbi_restore_file {} {}", quote_path(os_type, target), quote_unix_word(&backup_name(target))));
+        }
+    }
+    script.to_string()
+}
+
+//Prints what backup_boilerplate() moved out of the way, after everything was written.
+pub fn backed_up_boilerplate(os_type: &str) -> String {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln(&format!("if (Test-Path -LiteralPath \"$busbi_backup\\{}\") {{ Write-Host \"Backed up to ${{busbi_backup}}:\"; Get-Content -LiteralPath \"$busbi_backup\\{}\" }}", BACKUP_LIST, BACKUP_LIST));
+    } else {
+        script.string_ln(&format!("[ -f \"$busbi_backup\"/{} ] && echo \"Backed up to $busbi_backup:\" && cat \"$busbi_backup\"/{}", BACKUP_LIST, BACKUP_LIST));
+    }
+    script.to_string()
+}
+
+//Removes directories below the install directory, given deepest first, but only the ones that are
+//empty. Files the script didn't deliver keep their directory.
+pub fn removed_dirs_boilerplate(os_type: &str, install_paths: &[PathBuf], dest: &str) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if os_type.to_lowercase() == "windows" {
+        script.string_lines(POWERSHELL_RMDIR_FUNCTION);
+    }
+    for install_path in install_paths {
+        let mod_path : PathBuf = match adapt_path(install_path, os_type) {
+            Ok(x) => x,
+            Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
+        };
+        let dir_path : String = quote_path(os_type, &home_path(os_type, &[no_suffix_dest, &mod_path.to_string_lossy()]));
+        if os_type.to_lowercase() == "windows" {
+            script.string_ln(&format!("busbi_rmdir {}", dir_path));
         } else {
-            mf_string.push_str(format!(
-                "STRINGLN '@\n\
-                STRINGLN Set-Content -Path $HOME\\{} -Value $file\n\
-                ", dest).as_str());
+            script.string_ln(&format!("rmdir {} 2>/dev/null", dir_path));
+        }
+    }
+    script.to_string()
+}
 
+//Copies a file that was already written to the install directory, for files with the same content.
+//Both paths are relative to the install directory, like install_relative_path() gives them.
+pub fn copy_file_boilerplate(os_type: &str, original: &Path, install_path: &Path, dest: &str, mode: Option<u32>) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+    let adapt = |path: &Path| -> String {
+        match adapt_path(path, os_type) {
+            Ok(x) => x.to_string_lossy().into_owned(),
+            Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
         }
+    };
+    let original_path : String = home_path(os_type, &[no_suffix_dest, &adapt(original)]);
+    let copy_path : String = home_path(os_type, &[no_suffix_dest, &adapt(install_path)]);
+    let dir_path : String = home_path(os_type, &[no_suffix_dest, &adapt(install_path.parent().unwrap_or(Path::new("")))]);
+
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln(&format!("New-Item -ItemType Directory -Path {} -Force", quote_path(os_type, &dir_path)))
+            .string_ln(&format!("Copy-Item -LiteralPath {} -Destination {} -Force", quote_path(os_type, &original_path), quote_path(os_type, &copy_path)));
+    } else {
+        script.string_ln(&format!("mkdir -p {}", quote_path(os_type, &dir_path)))
+            .string_ln(&format!("cp {} {}", quote_path(os_type, &original_path), quote_path(os_type, &copy_path)));
+        add_permissions(&mut script, os_type, &copy_path, mode);
+    }
+    script.to_string()
+}
+
+//Recreates a symlink from the source tree. The link keeps pointing to exactly what it pointed to
+//on this machine, so relative links inside the tree keep working. Only called for Unix targets,
+//for Windows the links are copied when walking the directory.
+pub fn symlink_boilerplate(os_type: &str, install_path: &Path, link_target: &Path, dest: &str) -> String {
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+    let mod_path : PathBuf = match adapt_path(install_path, os_type) {
+        Ok(x) => x,
+        Err(e) => panic!("Unrecoverable error processing changes to path. Got: {:?}", e),
+    };
+    let mod_parent : PathBuf = match install_path.parent().map(|x| adapt_path(x, os_type)) {
+        Some(Ok(x)) => x,
+        _ => PathBuf::new(),
+    };
+    let link_path : String = home_path(os_type, &[no_suffix_dest, &mod_path.to_string_lossy()]);
+    let dir_path : String = home_path(os_type, &[no_suffix_dest, &mod_parent.to_string_lossy()]);
+
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    script.string_ln(&format!("mkdir -p {}", quote_path(os_type, &dir_path)))
+        .string_ln(&format!("ln -sfn {} {}", quote_unix_word(&link_target.to_string_lossy()), quote_path(os_type, &link_path)));
+    script.to_string()
+}
+
+//Writes the source file to exactly the path given by the user through '--map' or '--manifest',
+//creating the parent directories on the target first. 'mode' overrides the permissions of the
+//source file. Files that aren't text are typed as base64 and decoded on the target.
+pub fn mapped_file_boilerplate(os_type: &str, source_file: &Path, target: &str, mode: Option<u32>, content: &ContentOptions) -> String {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    let target_path : String = resolve_target_path(os_type, target);
+    let encode : bool = !is_text_file(source_file).unwrap_or(true);
+    let prompts : Vec<String> = if encode {Vec::new()} else {file_prompts(source_file, content)};
+    let target_parent : Option<&str> = match os_type.to_lowercase().as_str() {
+        "windows" => target_path.rsplit_once('\\').map(|x| x.0),
+        _ => target_path.rsplit_once('/').map(|x| x.0),
+    }.filter(|x| *x != "$HOME" && !x.is_empty());
+
+    if os_type.to_lowercase() == "windows" {
+        if let Some(parent) = target_parent {
+            script.string_ln(&format!("New-Item -ItemType Directory -Path {} -Force", quote_path(os_type, parent)));
+        }
+        script.string_ln("$file = @'");
+    } else if os_type.to_lowercase() == "unix" {
+        if let Some(parent) = target_parent {
+            script.string_ln(&format!("mkdir -p {}", quote_path(os_type, parent)));
+        }
+        if encode {
+            script.string_ln(&format!("base64 -d > {}", quote_path(os_type, &target_path)));
+        } else if prompts.is_empty() {
+            script.string_ln(&format!("cat > {}", quote_path(os_type, &target_path)));
+        } else {
+            script.string_ln(PROMPT_FILE_START);
+        }
+    }
+    if encode {
+        add_encoded_lines(&mut script, source_file);
+    } else {
+        add_file_lines(&mut script, source_file, content);
+    }
+    if os_type.to_lowercase() == "windows" && encode {
+        script.string_ln("'@")
+            .string_ln(&format!("[IO.File]::WriteAllBytes({}, [Convert]::FromBase64String($file))", quote_path(os_type, &target_path)));
+    } else if os_type.to_lowercase() == "windows" {
+        script.string_ln("'@");
+        add_filled_prompts(&mut script, os_type, &prompts, &target_path);
+        script.string_ln(&format!("Set-Content -LiteralPath {} -Value $file", quote_path(os_type, &target_path)));
     } else if os_type.to_lowercase() == "unix" {
-        mf_string.push_str(
-        "CTRL d\n\
-        ");
+        script.key("CTRL d");
+        add_filled_prompts(&mut script, os_type, &prompts, &target_path);
+        add_permissions(&mut script, os_type, &target_path, mode.or(changed_file_mode(source_file)));
+    }
+    script.to_string()
+}
+
+//What the operator is asked for when the archive is encrypted.
+const PASSPHRASE_PROMPT : &str = "passphrase";
+
+//Files with prompts are read into a shell variable on Unix targets instead of straight into the
+//file, so the answers can be put in before the file is written.
+const PROMPT_FILE_START : &str = "busbi_file=$(cat)";
+
+//Backups go below $HOME in here, one directory per run, see backup_boilerplate().
+pub const BACKUP_DIR : &str = ".busbi_backup";
+//Every backup directory lists what is in it, one path per line.
+pub const BACKUP_LIST : &str = "busbi-backed-up.txt";
+//Moves or, with a third argument, copies a file into the backup directory if it exists. The
+//helper functions are typed a few lines at a time, long STRING lines lose characters.
+pub const UNIX_BACKUP_FUNCTION : &[&str] = &[
+    "busbi_backup_file() {",
+    "[ -e \"$1\" ] || [ -L \"$1\" ] || return 0",
+    "mkdir -p \"$(dirname \"$busbi_backup/$2\")\" || return 1",
+    "if [ -n \"$3\" ]; then cp -pR \"$1\" \"$busbi_backup/$2\"; else mv \"$1\" \"$busbi_backup/$2\"; fi || return 1",
+    "echo \"$2\" >> \"$busbi_backup/busbi-backed-up.txt\"; }",
+];
+pub const POWERSHELL_BACKUP_FUNCTION : &[&str] = &[
+    "function busbi_backup_file($path, $name, $copy) {",
+    "if (-not (Test-Path -LiteralPath $path)) { return }",
+    "$to = Join-Path $busbi_backup $name; New-Item -ItemType Directory -Path (Split-Path $to) -Force | Out-Null",
+    "if ($copy) { Copy-Item -LiteralPath $path -Destination $to -Recurse -Force } else { Move-Item -LiteralPath $path -Destination $to -Force }",
+    "Add-Content -LiteralPath (Join-Path $busbi_backup 'busbi-backed-up.txt') -Value $name }",
+];
+//Moves a file back from the oldest backup directory of the script that has it.
+pub const UNIX_RESTORE_FUNCTION : &[&str] = &[
+    "busbi_restore_file() {",
+    "for b in \"$HOME\"/.busbi_backup/\"$busbi_backup_name\"-*/; do",
+    "if [ -e \"$b$2\" ] || [ -L \"$b$2\" ]; then mkdir -p \"$(dirname \"$1\")\" && mv \"$b$2\" \"$1\" && echo \"Restored $1\"; return; fi",
+    "done; }",
+];
+pub const POWERSHELL_RESTORE_FUNCTION : &[&str] = &[
+    "function busbi_restore_file($path, $name) {",
+    "foreach ($b in @(Get-ChildItem -Directory -LiteralPath \"$HOME\\.busbi_backup\" -Filter \"$busbi_backup_name-*\" -ErrorAction SilentlyContinue | Sort-Object Name)) {",
+    "$from = Join-Path $b.FullName $name; if (-not (Test-Path -LiteralPath $from)) { continue }",
+    "New-Item -ItemType Directory -Path (Split-Path $path) -Force | Out-Null; Move-Item -LiteralPath $from -Destination $path -Force",
+    "Write-Host \"Restored $path\"; return } }",
+];
+//Remove-Item asks before removing a directory that isn't empty, this leaves it instead.
+pub const POWERSHELL_RMDIR_FUNCTION : &[&str] = &[
+    "function busbi_rmdir($path) {",
+    "if ((Test-Path -LiteralPath $path) -and -not (Get-ChildItem -LiteralPath $path -Force)) { Remove-Item -LiteralPath $path } }",
+];
+//The execution policy from before '-x' is kept in the file in $busbi_policy, the first time only.
+pub const POWERSHELL_SAVE_POLICY : &str = "if (-not (Test-Path -LiteralPath $busbi_policy)) { New-Item -ItemType Directory -Path (Split-Path $busbi_policy) -Force | Out-Null; Get-ExecutionPolicy -Scope CurrentUser | Set-Content -LiteralPath $busbi_policy }";
+pub const POWERSHELL_RESTORE_POLICY : &str = "if (Test-Path -LiteralPath $busbi_policy) { Set-ExecutionPolicy (Get-Content -LiteralPath $busbi_policy) -Scope CurrentUser -Force; Remove-Item -LiteralPath $busbi_policy }";
+
+//Diffs are typed into a file next to the one they patch on Unix targets, removed afterwards.
+const PATCH_SUFFIX : &str = ".busbi-patch";
+
+//The prompts in a text file, only when the template pass is on.
+fn file_prompts(source_file: &Path, content: &ContentOptions) -> Vec<String> {
+    match content.template {
+        Some(_) => collect_prompts(&[source_file]),
+        None => Vec::new(),
+    }
+}
+
+//Asks the operator for the value of every prompt, right after the terminal opens. The input is
+//not echoed, and the Flipper waits for its button to be pressed after each question so the rest
+//of the script isn't typed into the prompt.
+pub fn prompts_boilerplate(os_type: &str, prompts: &[String]) -> String {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    add_prompts(&mut script, os_type, prompts);
+    script.to_string()
+}
+
+fn add_prompts(script: &mut ScriptBuilder, os_type: &str, prompts: &[String]) {
+    for name in prompts {
+        if os_type.to_lowercase() == "windows" {
+            script.string_ln(&format!("$busbi_{} = [Net.NetworkCredential]::new('', (Read-Host -AsSecureString '{}')).Password", name, name));
+        } else {
+            script.string_ln(&format!("printf '{}: '; read -rs busbi_{}; echo", name, name));
+        }
+        script.wait_for_button_press();
+    }
+}
+
+//Replaces the prompt markers in the typed file with the answers. On Windows the file is still in
+//$file, on Unix it is in $busbi_file and gets written here. The replacement is quoted so '&' and
+//'\' in an answer are taken literally.
+fn add_filled_prompts(script: &mut ScriptBuilder, os_type: &str, prompts: &[String], target_path: &str) {
+    if prompts.is_empty() {
+        return
     }
-    mf_string
+    for name in prompts {
+        if os_type.to_lowercase() == "windows" {
+            script.string_ln(&format!("$file = $file.Replace('{}', $busbi_{})", prompt_marker(name), name));
+        } else {
+            script.string_ln(&format!("busbi_file=${{busbi_file//{}/\"$busbi_{}\"}}", prompt_marker(name), name));
+        }
+    }
+    if os_type.to_lowercase() != "windows" {
+        script.string_ln(&format!("printf '%s\\n' \"$busbi_file\" > {}", quote_path(os_type, target_path)));
+    }
+}
+
+//Gives the file on the target the permissions in 'mode', nothing is typed for None. Only Unix
+//targets have permission bits, on Windows this adds nothing.
+fn add_permissions(script: &mut ScriptBuilder, os_type: &str, target_path: &str, mode: Option<u32>) {
+    if let Some(x) = mode && os_type.to_lowercase() == "unix" {
+        script.string_ln(&format!("chmod {:04o} {}", x, quote_path(os_type, target_path)));
+    }
+}
+
+//Types out every line of the source file. Shared by every way of writing a file to the target.
+fn add_file_lines(script: &mut ScriptBuilder, source_file: &Path, content: &ContentOptions) {
+    let lines : Vec<String> = match read_text_lines(source_file, content) {
+        Ok(x) => x,
+        Err(e) => panic!("Stopped due to error: {:?}", e),
+    };
+    let typed : ScriptBuilder = typed_lines(&lines);
+    if !content.minify {
+        script.append(typed);
+        return
+    }
+    match minify_lines(source_file, &lines) {
+        Some(minified) => {
+            let minified_typed : ScriptBuilder = typed_lines(&minified);
+            let saved : usize = typed.keystrokes().saturating_sub(minified_typed.keystrokes());
+            println!("Minifying {} saves {} keystrokes.", source_file.display(), saved);
+            script.append(minified_typed);
+        },
+        None => {script.append(typed);},
+    }
+}
+
+//The lines of a text file as they end up on the target, minified if asked for and with whitespace
+//only lines empty, see typed_lines(). '--patch' compares files like this.
+pub fn delivered_lines(source_file: &Path, content: &ContentOptions) -> Result<Vec<String>, HelpMessage> {
+    let mut lines : Vec<String> = read_text_lines(source_file, content)?;
+    if content.minify && let Some(minified) = minify_lines(source_file, &lines) {
+        lines = minified;
+    }
+    Ok(lines.into_iter().map(|x| if x.trim().is_empty() {String::new()} else {x}).collect())
+}
+
+//Reads the lines of a text file with the placeholders filled in. Templates are checked before
+//anything gets created, so an error here means the file changed in the meantime.
+pub fn read_text_lines(source_file: &Path, content: &ContentOptions) -> Result<Vec<String>, HelpMessage> {
+    let lines : Vec<String> = match read_lines(source_file) {
+        Ok(x) => x.map_while(Result::ok).collect(),
+        Err(_e) => {
+            println!("Err: File you specified doesn't exist or something else went wrong. Your file: {}", source_file.display());
+            return Err(HelpMessage::FailedToGetFile)
+        },
+    };
+    let vars : &TemplateVars = match &content.template {
+        Some(x) => x,
+        None => return Ok(lines),
+    };
+    let mut rendered : Vec<String> = Vec::new();
+    for (number, line) in lines.iter().enumerate() {
+        match render_line(line, vars) {
+            Ok(x) => rendered.push(x),
+            Err(e) => {println!("Error: {}:{}: {}.", source_file.display(), number + 1, e); return Err(HelpMessage::UndefinedVariable)},
+        }
+    }
+    Ok(rendered)
 }
 
-pub fn start_boilerplate(os_type: &str, is_dir : bool ,dest: &str) -> String {
+fn typed_lines(lines: &[String]) -> ScriptBuilder {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    for line in lines {
+        // Its necessary to check if the line is empty. The ducky script runs so fast that
+        // especially on windows a STRINGLN with nothing after will be printed out as
+        // "TRINGLN", without the "S" at the start, which powershell just loses for some
+        // reason. this check is to mitigate that and try to write the lines correctly.
+        match line.trim().is_empty() {
+            true => script.key("ENTER"),
+            false => script.string_ln(line),
+        };
+    }
+    script
+}
+
+//Types out the source file as base64, for files that can't be typed line by line.
+fn add_encoded_lines(script: &mut ScriptBuilder, source_file: &Path) {
+    let contents : Vec<u8> = match read(source_file) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Err: Could not read {}, got error: {}", source_file.display(), e);
+            panic!("Stopped due to above error.")
+        },
+    };
+    for line in base64_lines(&contents) {
+        script.string_ln(&line);
+    }
+}
+
+//Unpacks a compressed archive of the whole '-d' directory into the install directory. The archive
+//is typed as base64, Unix targets pipe it straight into tar, Windows targets write the zip to a
+//temporary file for Expand-Archive. An 'encrypted' archive is decrypted first with the passphrase
+//the operator types on the target, see crypto.rs.
+pub fn compressed_boilerplate(os_type: &str, archive: &[u8], dest: &str, encrypted: bool) -> String {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    let mod_dest : Vec<&str> = dest.split('.').collect();
+    let no_suffix_dest : &str = mod_dest[0];
+    let install_dir : String = home_path(os_type, &[no_suffix_dest]);
+
+    if encrypted {
+        add_prompts(&mut script, os_type, &[PASSPHRASE_PROMPT.to_string()]);
+    }
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln("$archive = @'");
+    } else if encrypted {
+        script.string_ln(&format!("export busbi_{}", PASSPHRASE_PROMPT))
+            .string_ln(&format!("base64 -d | openssl enc -d -aes-256-cbc -pbkdf2 -iter {} -md sha256 -pass env:busbi_{} | tar xzf - -C {}",
+                PBKDF2_ITERATIONS, PASSPHRASE_PROMPT, quote_path(os_type, &install_dir)));
+    } else {
+        script.string_ln(&format!("base64 -d | tar xzf - -C {}", quote_path(os_type, &install_dir)));
+    }
+    for line in base64_lines(archive) {
+        script.string_ln(&line);
+    }
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln("'@")
+            .string_ln("$archivePath = Join-Path $env:TEMP 'busbi.zip'")
+            .string_ln("$bytes = [Convert]::FromBase64String($archive)");
+        if encrypted {
+            add_decryption(&mut script);
+        }
+        script.string_ln("[IO.File]::WriteAllBytes($archivePath, $bytes)")
+            .string_ln(&format!("Expand-Archive -LiteralPath $archivePath -DestinationPath {} -Force", quote_path(os_type, &install_dir)))
+            .string_ln("Remove-Item -LiteralPath $archivePath");
+    } else {
+        script.key("CTRL d");
+        if encrypted {
+            script.string_ln(&format!("unset busbi_{}", PASSPHRASE_PROMPT));
+        }
+    }
+    script.to_string()
+}
+
+//Decrypts $bytes on Windows targets with the passphrase the operator put in. The same PBKDF2 and
+//AES-256-CBC openssl uses, the salt sits right after "Salted__".
+fn add_decryption(script: &mut ScriptBuilder) {
+    script.string_ln(&format!("$kdf = [Security.Cryptography.Rfc2898DeriveBytes]::new($busbi_{}, [byte[]]$bytes[8..15], {}, [Security.Cryptography.HashAlgorithmName]::SHA256)", PASSPHRASE_PROMPT, PBKDF2_ITERATIONS))
+        .string_ln("$aes = [Security.Cryptography.Aes]::Create(); $aes.Key = $kdf.GetBytes(32); $aes.IV = $kdf.GetBytes(16)")
+        .string_ln("$bytes = $aes.CreateDecryptor().TransformFinalBlock($bytes, 16, $bytes.Length - 16)")
+        .string_ln(&format!("Remove-Variable busbi_{}", PASSPHRASE_PROMPT));
+}
+
+//Types shell commands encrypted with encrypt_openssl() and runs them once they are decrypted on
+//the target, with the passphrase the operator puts in. Unix targets need 'openssl'.
+pub fn encrypted_commands_boilerplate(os_type: &str, encrypted: &[u8]) -> String {
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    add_prompts(&mut script, os_type, &[PASSPHRASE_PROMPT.to_string()]);
+    if os_type.to_lowercase() == "windows" {
+        script.string_ln("$payload = @'");
+        for line in base64_lines(encrypted) {
+            script.string_ln(&line);
+        }
+        script.string_ln("'@")
+            .string_ln("$bytes = [Convert]::FromBase64String($payload)");
+        add_decryption(&mut script);
+        script.string_ln("Invoke-Expression ([Text.Encoding]::UTF8.GetString($bytes))");
+    } else {
+        script.string_ln(&format!("export busbi_{}", PASSPHRASE_PROMPT))
+            .string_ln(&format!("eval \"$(base64 -d | openssl enc -d -aes-256-cbc -pbkdf2 -iter {} -md sha256 -pass env:busbi_{})\"", PBKDF2_ITERATIONS, PASSPHRASE_PROMPT));
+        for line in base64_lines(encrypted) {
+            script.string_ln(&line);
+        }
+        script.key("CTRL d")
+            .string_ln(&format!("unset busbi_{}", PASSPHRASE_PROMPT));
+    }
+    script.to_string()
+}
+
+//Roughly how many keys the Flipper presses to run the given part of a script, see
+//Command::keystrokes().
+pub fn count_keystrokes(script: &str) -> usize {
+    script.lines().filter_map(|x| parse_line(x.trim_start()).ok().flatten()).map(|x| x.keystrokes()).sum()
+}
+
+//Opens a shell on the target, after the header with where the script came from, see metadata.rs.
+pub fn start_boilerplate(os_type: &str, is_dir : bool ,dest: &str, metadata: &Metadata) -> String {
     
-    let mut os_start_string :String = String::new(); 
+    let mut script : ScriptBuilder = ScriptBuilder::new();
+    metadata.add_to(&mut script);
     let mod_dest : Vec<&str> = dest.split('.').collect();
     let no_suffix_dest : &str = mod_dest[0];
 
     if os_type.to_lowercase() == "windows" {
-        os_start_string.push_str(
-            "WINDOWS\n\
-            DELAY 400\n\
-            STRING powershell\n\
-            DELAY 200\n\
-            ENTER\n\
-            DELAY 1000\n\
-        ");
+        script.key("WINDOWS")
+            .delay(400)
+            .string("powershell")
+            .delay(200)
+            .key("ENTER")
+            .delay(1000);
         if is_dir {
-            os_start_string.push_str(format!(
-            "STRINGLN New-Item -Path \"$HOME\\{}\" -Type Directory\n\
-            ", no_suffix_dest).as_str());
+            script.string_ln(&format!("New-Item -Path {} -Type Directory", quote_path(os_type, &home_path(os_type, &[no_suffix_dest]))));
         }
         
     } else if os_type.to_lowercase() == "unix" {
-        os_start_string.push_str(
-            "GUI\n\
-            DELAY 400\n\
-            STRING terminal\n\
-            DELAY 200\n\
-            ENTER\n\
-            DELAY 400\n\
-        ");
+        script.key("GUI")
+            .delay(400)
+            .string("terminal")
+            .delay(200)
+            .key("ENTER")
+            .delay(400);
         if is_dir {
-            os_start_string.push_str(format!(
-                "STRINGLN mkdir $HOME/{}\n\
-            ", no_suffix_dest).as_str());
+            script.string_ln(&format!("mkdir {}", quote_path(os_type, &home_path(os_type, &[no_suffix_dest]))));
         }
     } 
-    os_start_string
+    script.to_string()
 
 }
 
@@ -257,24 +813,21 @@ pub fn start_boilerplate(os_type: &str, is_dir : bool ,dest: &str) -> String {
 //Use it as you see fit. Currently only runs if '-x' flag is NOT true.
 //TODO make this more soffisticated with '-x' so both can be true at the same time. 
 pub fn end_boilerplate(os_type: &str , close_window : bool) -> String {
-    let mut os_end_string: String = String::new();
+    let mut script : ScriptBuilder = ScriptBuilder::new();
     if close_window {
-        os_end_string.push_str("STRINGLN exit\n");
+        script.string_ln("exit");
 
 
     } else {
         if os_type.to_lowercase() == "windows" {
-            os_end_string.push_str(
-                "STRING end of programn ! Do what you want now...\n");
+            script.string("end of programn ! Do what you want now...");
 
         } else if os_type.to_lowercase() == "unix" {
-            os_end_string.push_str(
-                "STRING end of programn! Do other stuff here if you want now...\n");
+            script.string("end of programn! Do other stuff here if you want now...");
 
         } else {
             panic!("Something went wrong the boilerplate start function got the wrong OS type: {}", os_type);
         }
     }
-    os_end_string
+    script.to_string()
 }
-
@@ -0,0 +1,80 @@
+use std::env;
+
+use aes::Aes256;
+use aes::cipher::{BlockModeDecrypt, BlockModeEncrypt, KeyIvInit, block_padding::Pkcs7};
+use pbkdf2::pbkdf2_hmac;
+use rpassword::prompt_password;
+use sha2::Sha256;
+
+use crate::help_msg::HelpMessage;
+
+// Encrypts the packed '-d' archive with a passphrase, so a lost SD card doesn't give away the
+// files. The output is exactly what 'openssl enc -aes-256-cbc -pbkdf2 -iter 100000 -md sha256
+// -salt' writes: "Salted__", 8 bytes of salt and the ciphertext. Key and IV are the first 48 bytes
+// of PBKDF2-HMAC-SHA256 over the passphrase and salt.
+
+pub const PBKDF2_ITERATIONS : u32 = 100000;
+//Lets scripted builds give the passphrase without a prompt.
+pub const PASSPHRASE_VAR : &str = "BUSBI_PASSPHRASE";
+const SALT_HEADER : &[u8] = b"Salted__";
+
+pub fn encrypt_openssl(data: &[u8], passphrase: &str) -> Result<Vec<u8>, HelpMessage> {
+    let mut salt : [u8; 8] = [0; 8];
+    if let Err(e) = getrandom::fill(&mut salt) {
+        println!("Could not get random bytes for the salt, got error: {}", e);
+        return Err(HelpMessage::EncryptionFailed)
+    }
+    let mut key_iv : [u8; 48] = [0; 48];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &salt, PBKDF2_ITERATIONS, &mut key_iv);
+    let (key, iv) : (&[u8], &[u8]) = key_iv.split_at(32);
+    let encryptor = match cbc::Encryptor::<Aes256>::new_from_slices(key, iv) {
+        Ok(x) => x,
+        Err(e) => {println!("Could not set up AES, got error: {}", e); return Err(HelpMessage::EncryptionFailed)},
+    };
+    let mut encrypted : Vec<u8> = SALT_HEADER.to_vec();
+    encrypted.extend_from_slice(&salt);
+    encrypted.extend(encryptor.encrypt_padded_vec::<Pkcs7>(data));
+    Ok(encrypted)
+}
+
+//Reverses encrypt_openssl(), gives None when the passphrase is wrong or the data isn't encrypted.
+pub fn decrypt_openssl(data: &[u8], passphrase: &str) -> Option<Vec<u8>> {
+    let salt : &[u8] = data.strip_prefix(SALT_HEADER)?.get(..8)?;
+    let mut key_iv : [u8; 48] = [0; 48];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ITERATIONS, &mut key_iv);
+    let (key, iv) : (&[u8], &[u8]) = key_iv.split_at(32);
+    let decryptor = cbc::Decryptor::<Aes256>::new_from_slices(key, iv).ok()?;
+    decryptor.decrypt_padded_vec::<Pkcs7>(&data[SALT_HEADER.len() + 8..]).ok()
+}
+
+//Tells what encrypt_openssl() wrote apart from plain data.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(SALT_HEADER)
+}
+
+//The passphrase of something encrypted before, from BUSBI_PASSPHRASE or asked for once.
+pub fn existing_passphrase() -> Result<String, HelpMessage> {
+    if let Ok(x) = env::var(PASSPHRASE_VAR) && !x.is_empty() {
+        return Ok(x)
+    }
+    prompt_password("Passphrase of the payload: ").map_err(|_e| HelpMessage::NoPassphrase)
+}
+
+//Takes the passphrase from BUSBI_PASSPHRASE or asks for it twice without echoing it. It is only
+//ever used here, the script asks the operator for it on the target.
+pub fn read_passphrase() -> Result<String, HelpMessage> {
+    if let Ok(x) = env::var(PASSPHRASE_VAR) && !x.is_empty() {
+        return Ok(x)
+    }
+    let first : String = prompt_password("Passphrase for the payload: ").map_err(|_e| HelpMessage::NoPassphrase)?;
+    let second : String = prompt_password("Repeat the passphrase: ").map_err(|_e| HelpMessage::NoPassphrase)?;
+    if first.is_empty() {
+        println!("Error: the passphrase can't be empty.");
+        return Err(HelpMessage::NoPassphrase)
+    }
+    if first != second {
+        println!("Error: the passphrases don't match.");
+        return Err(HelpMessage::NoPassphrase)
+    }
+    Ok(first)
+}
@@ -0,0 +1,342 @@
+use std::fmt;
+
+// A typed form of the Flipper BadUSB format, the DuckyScript 1.0 dialect the Flipper Zero runs:
+// https://developer.flipper.net/flipperzero/doxygen/badusb_file_format.html
+// The generators in boilerplates.rs build their output with ScriptBuilder, and the commands that
+// read scripts, like lint, get a Command per line out of parse_line().
+
+//Modifiers that can start a key combination, on their own or joined with '-'.
+pub const MODIFIERS : [&str; 12] = [
+    "CTRL", "CONTROL", "SHIFT", "ALT", "GUI", "WINDOWS", "CTRL-ALT", "CTRL-SHIFT", "ALT-SHIFT",
+    "ALT-GUI", "GUI-SHIFT", "GUI-CTRL",
+];
+
+//Named keys, besides F1 to F12 and single characters.
+pub const KEYS : [&str; 29] = [
+    "DOWNARROW", "DOWN", "LEFTARROW", "LEFT", "RIGHTARROW", "RIGHT", "UPARROW", "UP", "ENTER",
+    "BREAK", "PAUSE", "CAPSLOCK", "DELETE", "BACKSPACE", "END", "ESC", "ESCAPE", "HOME", "INSERT",
+    "NUMLOCK", "PAGEUP", "PAGEDOWN", "PRINTSCREEN", "SCROLLLOCK", "SPACE", "TAB", "MENU", "APP",
+    "GLOBE",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Rem(String),
+    //'VID:PID Manufacturer:Product' the Flipper shows itself as.
+    Id(String),
+    Delay(u64),
+    DefaultDelay(u64),
+    String(String),
+    StringLn(String),
+    StringDelay(u64),
+    DefaultStringDelay(u64),
+    //Runs the command before it this many more times.
+    Repeat(u64),
+    Hold(String),
+    Release(String),
+    WaitForButtonPress,
+    AltChar(u64),
+    AltString(String),
+    AltCode(String),
+    SysRq(String),
+    Media(String),
+    MouseMove(i64, i64),
+    MouseScroll(i64),
+    //A key or key combination as written, like ["ENTER"], ["GUI", "r"] or ["CTRL-ALT", "DELETE"].
+    Key(Vec<String>),
+}
+
+impl Command {
+    //Roughly how many keys the Flipper presses for this command. Every typed character is one key
+    //press, STRINGLN adds an ENTER, commands that only wait or configure press nothing and any
+    //other command is a single key or key combination.
+    pub fn keystrokes(&self) -> usize {
+        match self {
+            Command::StringLn(x) => x.chars().count() + 1,
+            Command::String(x) | Command::AltString(x) | Command::AltCode(x) => x.chars().count(),
+            Command::Rem(_) | Command::Id(_) | Command::Delay(_) | Command::DefaultDelay(_) | Command::StringDelay(_)
+                | Command::DefaultStringDelay(_) | Command::WaitForButtonPress | Command::Repeat(_) => 0,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Rem(x) if x.is_empty() => write!(f, "REM"),
+            Command::Rem(x) => write!(f, "REM {}", x),
+            Command::Id(x) => write!(f, "ID {}", x),
+            Command::Delay(x) => write!(f, "DELAY {}", x),
+            Command::DefaultDelay(x) => write!(f, "DEFAULT_DELAY {}", x),
+            Command::String(x) => write!(f, "STRING {}", x),
+            Command::StringLn(x) => write!(f, "STRINGLN {}", x),
+            Command::StringDelay(x) => write!(f, "STRING_DELAY {}", x),
+            Command::DefaultStringDelay(x) => write!(f, "DEFAULT_STRING_DELAY {}", x),
+            Command::Repeat(x) => write!(f, "REPEAT {}", x),
+            Command::Hold(x) => write!(f, "HOLD {}", x),
+            Command::Release(x) => write!(f, "RELEASE {}", x),
+            Command::WaitForButtonPress => write!(f, "WAIT_FOR_BUTTON_PRESS"),
+            Command::AltChar(x) => write!(f, "ALTCHAR {}", x),
+            Command::AltString(x) => write!(f, "ALTSTRING {}", x),
+            Command::AltCode(x) => write!(f, "ALTCODE {}", x),
+            Command::SysRq(x) => write!(f, "SYSRQ {}", x),
+            Command::Media(x) => write!(f, "MEDIA {}", x),
+            Command::MouseMove(x, y) => write!(f, "MOUSE_MOVE {} {}", x, y),
+            Command::MouseScroll(x) => write!(f, "MOUSE_SCROLL {}", x),
+            Command::Key(keys) => write!(f, "{}", keys.join(" ")),
+        }
+    }
+}
+
+//Parses a single line. Empty lines give None, the Flipper skips them.
+pub fn parse_line(line: &str) -> Result<Option<Command>, String> {
+    if line.trim().is_empty() {
+        return Ok(None)
+    }
+    if line.starts_with(char::is_whitespace) {
+        return Err("whitespace before the command, the Flipper won't recognise it".to_string())
+    }
+    let (command, argument) : (&str, Option<&str>) = match line.split_once(' ') {
+        Some((x, y)) => (x, Some(y)),
+        None => (line, None),
+    };
+    let text = |name: &str| -> Result<String, String> {
+        match argument {
+            Some(x) if !x.is_empty() => Ok(x.to_string()),
+            _ => Err(format!("{} without any text", name)),
+        }
+    };
+    let number = |name: &str| -> Result<u64, String> {
+        argument.and_then(|x| x.trim().parse::<u64>().ok()).ok_or(format!("{} needs a number", name))
+    };
+    let key = |name: &str| -> Result<String, String> {
+        match argument.map(|x| x.trim()) {
+            Some(x) if !x.is_empty() => Ok(x.to_string()),
+            _ => Err(format!("{} needs a key", name)),
+        }
+    };
+    let parsed : Command = match command {
+        "REM" => Command::Rem(argument.unwrap_or("").to_string()),
+        "ID" => match argument {
+            Some(x) if x.contains(':') => Command::Id(x.to_string()),
+            _ => return Err("ID needs 'VID:PID Manufacturer:Product'".to_string()),
+        },
+        "DELAY" => Command::Delay(number(command)?),
+        "DEFAULT_DELAY" | "DEFAULTDELAY" => Command::DefaultDelay(number(command)?),
+        "STRING" => Command::String(text(command)?),
+        "STRINGLN" => Command::StringLn(text(command)?),
+        "STRING_DELAY" | "STRINGDELAY" => Command::StringDelay(number(command)?),
+        "DEFAULT_STRING_DELAY" | "DEFAULTSTRINGDELAY" => Command::DefaultStringDelay(number(command)?),
+        "REPEAT" => Command::Repeat(number(command)?),
+        "HOLD" => Command::Hold(key(command)?),
+        "RELEASE" => Command::Release(key(command)?),
+        "WAIT_FOR_BUTTON_PRESS" => match argument {
+            None => Command::WaitForButtonPress,
+            Some(_) => return Err("WAIT_FOR_BUTTON_PRESS takes no argument".to_string()),
+        },
+        "ALTCHAR" => Command::AltChar(number(command)?),
+        "ALTSTRING" => Command::AltString(text(command)?),
+        "ALTCODE" => Command::AltCode(text(command)?),
+        "SYSRQ" => Command::SysRq(key(command)?),
+        "MEDIA" => Command::Media(key(command)?),
+        "MOUSE_MOVE" | "MOUSEMOVE" => {
+            let numbers : Vec<i64> = argument.unwrap_or("").split_whitespace().filter_map(|x| x.parse().ok()).collect();
+            match numbers[..] {
+                [x, y] if argument.is_some_and(|a| a.split_whitespace().count() == 2) => Command::MouseMove(x, y),
+                _ => return Err(format!("{} needs an x and a y offset", command)),
+            }
+        },
+        "MOUSE_SCROLL" | "MOUSESCROLL" => match argument.and_then(|x| x.trim().parse::<i64>().ok()) {
+            Some(x) => Command::MouseScroll(x),
+            None => return Err(format!("{} needs a number", command)),
+        },
+        _ => Command::Key(parse_key_combo(line)?),
+    };
+    Ok(Some(parsed))
+}
+
+//Parses a whole script, the error has the number of the first line that isn't valid and what is
+//wrong with it.
+pub fn parse_script(script: &str) -> Result<Vec<Command>, (usize, String)> {
+    let mut commands : Vec<Command> = Vec::new();
+    for (index, line) in script.lines().enumerate() {
+        if let Some(x) = parse_line(line).map_err(|e| (index + 1, e))? {
+            commands.push(x);
+        }
+    }
+    Ok(commands)
+}
+
+//Checks a line of key presses like 'ENTER', 'GUI r' or 'CTRL-ALT DELETE'.
+fn parse_key_combo(line: &str) -> Result<Vec<String>, String> {
+    if line.ends_with(char::is_whitespace) {
+        return Err(format!("trailing whitespace after '{}'", line.trim_end()))
+    }
+    let parts : Vec<&str> = line.split(' ').collect();
+    if parts.iter().any(|x| x.is_empty()) {
+        return Err(format!("more than one space between the keys of '{}'", line))
+    }
+    let (first, rest) : (&str, &[&str]) = (parts[0], &parts[1..]);
+    if MODIFIERS.contains(&first) {
+        if rest.len() > 1 {
+            return Err(format!("'{}' presses more than one key after the modifier", line))
+        }
+        if let Some(key) = rest.first() && !is_key(key) {
+            return Err(format!("unknown key '{}'", key))
+        }
+    } else if !rest.is_empty() {
+        if first.chars().all(|c| c.is_ascii_uppercase() || c == '_') && !is_key(first) {
+            return Err(format!("unknown command '{}'", first))
+        }
+        return Err(format!("'{}' is not a key combination, use STRING to type text", line))
+    } else if !is_key(first) {
+        return Err(format!("unknown command or key '{}'", first))
+    }
+    Ok(parts.iter().map(|x| x.to_string()).collect())
+}
+
+pub fn is_key(key: &str) -> bool {
+    if KEYS.contains(&key) || MODIFIERS.contains(&key) || key.chars().count() == 1 {
+        return true
+    }
+    match key.strip_prefix('F') {
+        Some(x) => x.parse::<u8>().is_ok_and(|x| (1..=12).contains(&x)),
+        None => false,
+    }
+}
+
+//Writes commands back as a script, one per line.
+pub fn to_script(commands: &[Command]) -> String {
+    commands.iter().map(|x| format!("{}\n", x)).collect()
+}
+
+//The text the commands type, with a line break for every STRINGLN and ENTER. Anything else they
+//do is left out, so this only fits commands that do nothing but type shell lines.
+pub fn typed_text(commands: &[Command]) -> String {
+    let mut text : String = String::new();
+    for command in commands {
+        match command {
+            Command::String(x) => text.push_str(x),
+            Command::StringLn(x) => {text.push_str(x); text.push('\n')},
+            Command::Key(keys) if keys.len() == 1 && keys[0] == "ENTER" => text.push('\n'),
+            _ => (),
+        }
+    }
+    text
+}
+
+//Collects the commands of a script, so generators don't have to format DuckyScript themselves.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptBuilder {
+    commands: Vec<Command>,
+}
+
+impl ScriptBuilder {
+    pub fn new() -> ScriptBuilder {
+        ScriptBuilder::default()
+    }
+
+    pub fn push(&mut self, command: Command) -> &mut ScriptBuilder {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn rem(&mut self, text: &str) -> &mut ScriptBuilder {
+        self.push(Command::Rem(text.to_string()))
+    }
+
+    pub fn delay(&mut self, milliseconds: u64) -> &mut ScriptBuilder {
+        self.push(Command::Delay(milliseconds))
+    }
+
+    pub fn string(&mut self, text: &str) -> &mut ScriptBuilder {
+        self.push(Command::String(text.to_string()))
+    }
+
+    pub fn string_ln(&mut self, text: &str) -> &mut ScriptBuilder {
+        self.push(Command::StringLn(text.to_string()))
+    }
+
+    //Types each line with STRINGLN.
+    pub fn string_lines(&mut self, lines: &[&str]) -> &mut ScriptBuilder {
+        for line in lines {
+            self.string_ln(line);
+        }
+        self
+    }
+
+    //A key or combination as written in a script, like "ENTER" or "CTRL d".
+    pub fn key(&mut self, keys: &str) -> &mut ScriptBuilder {
+        self.push(Command::Key(keys.split(' ').map(|x| x.to_string()).collect()))
+    }
+
+    pub fn wait_for_button_press(&mut self) -> &mut ScriptBuilder {
+        self.push(Command::WaitForButtonPress)
+    }
+
+    pub fn append(&mut self, other: ScriptBuilder) -> &mut ScriptBuilder {
+        self.commands.extend(other.commands);
+        self
+    }
+
+    pub fn keystrokes(&self) -> usize {
+        self.commands.iter().map(|x| x.keystrokes()).sum()
+    }
+
+}
+
+impl fmt::Display for ScriptBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", to_script(&self.commands))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT : &str = "REM busbi-version: 0.2.1\nREM\nID 1234:abcd Maker:Keyboard\nDEFAULT_DELAY 10\nDELAY 400\n\
+        GUI r\nCTRL-ALT DELETE\nALT-SHIFT\nGUI-CTRL t\nENTER\nF12\nSTRING powershell -c \"a  b\" $x 'y'\n\
+        STRINGLN   leading spaces and trailing ones  \nSTRING_DELAY 5\nREPEAT 2\nHOLD SHIFT\nRELEASE SHIFT\n\
+        WAIT_FOR_BUTTON_PRESS\nALTCHAR 65\nMOUSE_MOVE -10 20\nMOUSE_SCROLL -3\n";
+
+    #[test]
+    fn scripts_survive_a_round_trip() {
+        let commands : Vec<Command> = parse_script(SCRIPT).unwrap();
+        assert_eq!(to_script(&commands), SCRIPT);
+        assert_eq!(parse_script(&to_script(&commands)).unwrap(), commands);
+        assert_eq!(commands[11], Command::String("powershell -c \"a  b\" $x 'y'".to_string()));
+        assert_eq!(commands[12], Command::StringLn("  leading spaces and trailing ones  ".to_string()));
+        assert_eq!(commands[6], Command::Key(vec!["CTRL-ALT".to_string(), "DELETE".to_string()]));
+    }
+
+    #[test]
+    fn old_spellings_and_blank_lines_are_written_the_current_way() {
+        let commands : Vec<Command> = parse_script("DEFAULTDELAY 5\n\nSTRINGDELAY 3\nMOUSEMOVE 1 2\n").unwrap();
+        assert_eq!(to_script(&commands), "DEFAULT_DELAY 5\nSTRING_DELAY 3\nMOUSE_MOVE 1 2\n");
+        assert_eq!(parse_script(&to_script(&commands)).unwrap(), commands);
+    }
+
+    #[test]
+    fn malformed_lines_give_the_line_number() {
+        for (script, line) in [
+            ("DELAY 1\nDELAY x", 2), ("STRING", 1), ("STRINGLN ", 1), (" STRING a", 1), ("ENTER ", 1),
+            ("GUI  r", 1), ("CTRL a b", 1), ("GUI F13", 1), ("FOO", 1), ("TYPE this", 1),
+            ("echo hi", 1), ("ID 1234", 1), ("WAIT_FOR_BUTTON_PRESS now", 1), ("REM\nMOUSE_MOVE 1", 2),
+        ] {
+            assert_eq!(parse_script(script).map_err(|e| e.0), Err(line), "{}", script);
+        }
+    }
+
+    #[test]
+    fn built_scripts_parse_back_to_their_commands() {
+        let mut script : ScriptBuilder = ScriptBuilder::new();
+        script.rem("made by a test").key("GUI r").delay(200).string("cmd").key("ENTER")
+            .string_lines(&["echo a", "echo b"]).key("CTRL d").wait_for_button_press();
+        let commands : Vec<Command> = parse_script(&script.to_string()).unwrap();
+        assert_eq!(to_script(&commands), script.to_string());
+        assert_eq!(commands.iter().map(|x| x.keystrokes()).sum::<usize>(), script.keystrokes());
+        assert_eq!(typed_text(&commands), "cmd\necho a\necho b\n");
+    }
+}
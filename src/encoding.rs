@@ -0,0 +1,88 @@
+// Encodings for getting content through a keyboard, which can only type text.
+
+const BASE64_ALPHABET : &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+//How long each typed line of base64 is, the same as what the 'base64' tool writes.
+pub const BASE64_LINE_LENGTH : usize = 76;
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut encoded : String = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes : [u8; 3] = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group : u32 = ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+//Splits base64 into lines of BASE64_LINE_LENGTH characters.
+pub fn base64_lines(data: &[u8]) -> Vec<String> {
+    let encoded : String = base64_encode(data);
+    encoded.as_bytes().chunks(BASE64_LINE_LENGTH).map(|x| String::from_utf8_lossy(x).into_owned()).collect()
+}
+
+//Decodes base64, line breaks and other whitespace are skipped like 'base64 -d' does. Gives None
+//for anything that isn't valid base64.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut decoded : Vec<u8> = Vec::with_capacity(text.len() / 4 * 3);
+    let mut group : u32 = 0;
+    let mut count : usize = 0;
+    let mut padding : usize = 0;
+    for c in text.bytes().filter(|x| !x.is_ascii_whitespace()) {
+        let value : u32 = match c {
+            b'=' if padding < 2 => {padding += 1; 0},
+            //A group never has more than two '=' and nothing comes after them.
+            _ if padding > 0 || c == b'=' => return None,
+            _ => BASE64_ALPHABET.iter().position(|x| *x == c)? as u32,
+        };
+        group = (group << 6) | value;
+        count += 1;
+        if count == 4 {
+            let bytes : [u8; 3] = [(group >> 16) as u8, (group >> 8) as u8, group as u8];
+            decoded.extend_from_slice(&bytes[..3 - padding]);
+            group = 0;
+            count = 0;
+        }
+    }
+    if count != 0 {
+        return None
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_padding_length() {
+        let expected : [&str; 4] = ["", "Zg==", "Zm8=", "Zm9v"];
+        for (length, encoded) in expected.iter().enumerate() {
+            let data : &[u8] = &b"foo"[..length];
+            assert_eq!(base64_encode(data), *encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn round_trips_every_byte_value() {
+        let data : Vec<u8> = (0..=255).collect();
+        let lines : Vec<String> = base64_lines(&data);
+        assert!(lines.iter().all(|x| x.len() <= BASE64_LINE_LENGTH));
+        assert_eq!(base64_decode(&lines.join("\n")).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        //Not in the alphabet, an incomplete group, too much padding and data after padding.
+        for text in ["Zm9*", "Zm9", "Z===", "====", "Zg==Zg==", "Zg=v"] {
+            assert_eq!(base64_decode(text), None, "{}", text);
+        }
+    }
+}
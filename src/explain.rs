@@ -0,0 +1,197 @@
+use std::env;
+use std::fs::{read_to_string, remove_dir_all};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::boilerplates::count_keystrokes;
+use crate::simulate::{Event, Simulator, replay_script};
+
+// 'busbi explain <file.txt>' says what a bad USB script will do on the target, so it can be
+// reviewed before it runs on someone's machine: the launcher, every directory and file it creates,
+// execution policy changes, what gets executed and whether the window closes. The script is
+// replayed into a temporary directory like 'busbi simulate' does, which is removed afterwards.
+
+#[derive(Debug, Default)]
+struct Explanation {
+    target_os: String,
+    launcher: Option<String>,
+    keystrokes: usize,
+    prompts: Vec<String>,
+    directories: Vec<String>,
+    //Files still there when the script is done, with their size in bytes.
+    files: Vec<(String, u64)>,
+    links: Vec<(String, String)>,
+    removed: Vec<String>,
+    //Files that were already there, moved or copied out of the way first.
+    backed_up: Vec<(String, String)>,
+    //Files moved back from a backup.
+    restored: Vec<(String, String)>,
+    policy_changes: Vec<String>,
+    executed: Vec<String>,
+    //Every other command typed into the shell.
+    commands: Vec<String>,
+    closes_window: bool,
+    errors: usize,
+}
+
+//Runs 'busbi explain' and gives the exit code, 1 if the script fails somewhere.
+pub fn explain_command(args: &[String]) -> i32 {
+    let mut script_path : Option<&str> = None;
+    let mut windows : Option<bool> = None;
+    let mut json : bool = false;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" | "--os" => match rest.next().map(|x| x.to_lowercase()).as_deref() {
+                Some("windows") => windows = Some(true),
+                Some("unix") => windows = Some(false),
+                _ => {println!("Error: '--os' needs 'windows' or 'unix'."); return 2},
+            },
+            "--format" => match rest.next().map(|x| x.as_str()) {
+                Some("text") => json = false,
+                Some("json") => json = true,
+                _ => {println!("Error: '--format' needs 'text' or 'json'."); return 2},
+            },
+            x if script_path.is_none() => script_path = Some(x),
+            x => {println!("Error: unexpected argument '{}'.", x); return 2},
+        }
+    }
+    let script_path : &Path = match script_path {
+        Some(x) => Path::new(x),
+        None => {println!("Error: give the bad USB script to explain, like 'busbi explain script.txt'."); return 2},
+    };
+    let root : PathBuf = env::temp_dir().join(format!("busbi-explain-{}", process::id()));
+    let simulator : Result<Simulator, i32> = replay_script(script_path, windows, &root, false);
+    let _ = remove_dir_all(&root);
+    let simulator : Simulator = match simulator {
+        Ok(x) => x,
+        Err(code) => return code,
+    };
+    let mut explanation : Explanation = explain(&simulator);
+    explanation.keystrokes = read_to_string(script_path).map(|x| count_keystrokes(&x)).unwrap_or(0);
+    if json {
+        println!("{}", to_json(&explanation));
+    } else {
+        print_text(&explanation);
+    }
+    if explanation.errors > 0 {1} else {0}
+}
+
+fn explain(simulator: &Simulator) -> Explanation {
+    let mut explanation = Explanation {
+        target_os: if simulator.windows {"windows"} else {"unix"}.to_string(),
+        errors: simulator.errors,
+        ..Default::default()
+    };
+    let add = |list: &mut Vec<String>, value: &str| {
+        if !list.iter().any(|x| x == value) {
+            list.push(value.to_string());
+        }
+    };
+    for event in &simulator.events {
+        match event {
+            Event::Launched(x) => explanation.launcher = Some(x.clone()),
+            Event::OpenedTerminal => explanation.launcher = Some("terminal (CTRL-ALT t)".to_string()),
+            Event::MadeDir(x) => add(&mut explanation.directories, x),
+            //A file written twice is listed once, with its last size.
+            Event::Wrote(x, size) | Event::Copied(_, x, size) => {
+                explanation.files.retain(|y| y.0 != *x);
+                explanation.files.push((x.clone(), *size));
+            },
+            Event::Linked(x, y) => explanation.links.push((x.clone(), y.clone())),
+            Event::Removed(x) => {
+                explanation.files.retain(|y| y.0 != *x);
+                add(&mut explanation.removed, x);
+            },
+            Event::BackedUp(x, y) => explanation.backed_up.push((x.clone(), y.clone())),
+            Event::Restored(x, y) => explanation.restored.push((x.clone(), y.clone())),
+            Event::Prompted(x) => add(&mut explanation.prompts, x),
+            Event::ChangedPolicy(x) => add(&mut explanation.policy_changes, x),
+            Event::Executed(x) => explanation.executed.push(x.clone()),
+            Event::Ran(x) => explanation.commands.push(x.clone()),
+            Event::Pressed(x) => explanation.commands.push(format!("(key) {}", x)),
+            Event::SetMode(..) => (),
+            Event::Closed => explanation.closes_window = true,
+        }
+    }
+    explanation
+}
+
+fn print_text(explanation: &Explanation) {
+    let list = |title: &str, items: Vec<String>| {
+        if items.is_empty() {
+            println!("{}: none", title);
+        } else {
+            println!("{} ({}):", title, items.len());
+            for item in items {
+                println!("  {}", item);
+            }
+        }
+    };
+    println!("Target: {}", explanation.target_os);
+    println!("Launcher: {}", explanation.launcher.as_deref().unwrap_or("none, it types into whatever has focus"));
+    println!("Keystrokes: about {}", explanation.keystrokes);
+    list("Asks the operator for", explanation.prompts.clone());
+    list("Directories created", explanation.directories.clone());
+    list("Files written", explanation.files.iter().map(|(x, size)| format!("{} ({} bytes)", x, size)).collect());
+    list("Links created", explanation.links.iter().map(|(x, y)| format!("{} -> {}", x, y)).collect());
+    list("Files removed", explanation.removed.clone());
+    list("Files backed up", explanation.backed_up.iter().map(|(x, y)| format!("{} -> {}", x, y)).collect());
+    list("Files restored", explanation.restored.iter().map(|(x, y)| format!("{} <- {}", x, y)).collect());
+    list("Execution policy changes", explanation.policy_changes.clone());
+    list("Scripts executed", explanation.executed.clone());
+    list("Other commands", explanation.commands.clone());
+    println!("Window: {}", if explanation.closes_window {"closed at the end"} else {"stays open"});
+    if explanation.errors > 0 {
+        println!("\x1b[31mThe script fails {} time(s) on the way, see 'busbi simulate'.\x1b[0m", explanation.errors);
+    }
+}
+
+fn to_json(explanation: &Explanation) -> String {
+    let strings = |items: &[String]| -> String {
+        format!("[{}]", items.iter().map(|x| json_string(x)).collect::<Vec<String>>().join(", "))
+    };
+    let files : Vec<String> = explanation.files.iter()
+        .map(|(x, size)| format!("{{\"path\": {}, \"size\": {}}}", json_string(x), size)).collect();
+    let links : Vec<String> = explanation.links.iter()
+        .map(|(x, y)| format!("{{\"path\": {}, \"target\": {}}}", json_string(x), json_string(y))).collect();
+    let backed_up : Vec<String> = explanation.backed_up.iter()
+        .map(|(x, y)| format!("{{\"path\": {}, \"backup\": {}}}", json_string(x), json_string(y))).collect();
+    let restored : Vec<String> = explanation.restored.iter()
+        .map(|(x, y)| format!("{{\"path\": {}, \"backup\": {}}}", json_string(x), json_string(y))).collect();
+    let fields : Vec<(&str, String)> = vec![
+        ("target_os", json_string(&explanation.target_os)),
+        ("launcher", explanation.launcher.as_deref().map(json_string).unwrap_or("null".to_string())),
+        ("keystrokes", explanation.keystrokes.to_string()),
+        ("prompts", strings(&explanation.prompts)),
+        ("directories", strings(&explanation.directories)),
+        ("files", format!("[{}]", files.join(", "))),
+        ("links", format!("[{}]", links.join(", "))),
+        ("removed", strings(&explanation.removed)),
+        ("backed_up", format!("[{}]", backed_up.join(", "))),
+        ("restored", format!("[{}]", restored.join(", "))),
+        ("policy_changes", strings(&explanation.policy_changes)),
+        ("executed", strings(&explanation.executed)),
+        ("commands", strings(&explanation.commands)),
+        ("closes_window", explanation.closes_window.to_string()),
+        ("errors", explanation.errors.to_string()),
+    ];
+    let body : Vec<String> = fields.iter().map(|(name, value)| format!("  \"{}\": {}", name, value)).collect();
+    format!("{{\n{}\n}}", body.join(",\n"))
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted : String = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
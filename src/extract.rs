@@ -0,0 +1,115 @@
+use std::fs::write;
+use std::path::{Path, PathBuf};
+
+use crate::simulate::{Event, Simulator, replay_script};
+
+// 'busbi extract <file.txt>' gets the files back out of a bad USB script busbi made, for when the
+// script on the Flipper is all that is left. The script is replayed like 'busbi simulate' does, so
+// every file ends up below the output directory under its path on the target. A manifest in the
+// same format '--manifest' reads lists where each file goes, with the execute and close steps as
+// comments, so the script can be made again from the extracted files.
+
+const MANIFEST_NAME : &str = "busbi_manifest.txt";
+
+//Runs 'busbi extract' and gives the exit code, 1 if the script failed somewhere.
+pub fn extract_command(args: &[String]) -> i32 {
+    let mut script_path : Option<&str> = None;
+    let mut windows : Option<bool> = None;
+    let mut out : Option<PathBuf> = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" | "--os" => match rest.next().map(|x| x.to_lowercase()).as_deref() {
+                Some("windows") => windows = Some(true),
+                Some("unix") => windows = Some(false),
+                _ => {println!("Error: '--os' needs 'windows' or 'unix'."); return 2},
+            },
+            "--out" => match rest.next() {
+                Some(x) => out = Some(PathBuf::from(x)),
+                None => {println!("Error: '--out' needs a directory."); return 2},
+            },
+            x if script_path.is_none() => script_path = Some(x),
+            x => {println!("Error: unexpected argument '{}'.", x); return 2},
+        }
+    }
+    let script_path : &Path = match script_path {
+        Some(x) => Path::new(x),
+        None => {println!("Error: give the bad USB script to extract, like 'busbi extract script.txt'."); return 2},
+    };
+    //'old_payload.txt' is extracted to 'old_payload_extracted' by default.
+    let out : PathBuf = out.unwrap_or_else(|| {
+        let stem : String = script_path.file_stem().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+        PathBuf::from(format!("{}_extracted", stem))
+    });
+    let simulator : Simulator = match replay_script(script_path, windows, &out, true) {
+        Ok(x) => x,
+        Err(code) => return code,
+    };
+    let manifest : String = build_manifest(&simulator, script_path);
+    if let Err(e) = write(out.join(MANIFEST_NAME), manifest) {
+        println!("Could not write the manifest, got error: {}", e);
+        return 2
+    }
+    println!("Extracted the files to {}, see {} for where they go.", out.display(), out.join(MANIFEST_NAME).display());
+    if simulator.errors > 0 {1} else {0}
+}
+
+fn build_manifest(simulator: &Simulator, script_path: &Path) -> String {
+    let os_type : &str = if simulator.windows {"windows"} else {"unix"};
+    //Every target path that still holds a file at the end, in the order it was first written.
+    let mut files : Vec<&str> = Vec::new();
+    let mut notes : Vec<String> = Vec::new();
+    let mut executed : Option<&str> = None;
+    let mut has_prompts : bool = false;
+    for event in &simulator.events {
+        match event {
+            Event::Wrote(target, _size) | Event::Copied(_, target, _size) if !files.contains(&target.as_str()) => files.push(target),
+            Event::MadeDir(x) => notes.push(format!("Directory: {}", x)),
+            Event::Linked(x, y) => notes.push(format!("Link: {} -> {}", x, y)),
+            Event::Prompted(x) => {has_prompts = true; notes.push(format!("Prompt: {}", x))},
+            Event::ChangedPolicy(x) | Event::Ran(x) => notes.push(format!("Ran: {}", x)),
+            Event::Executed(x) => {executed = Some(x); notes.push(format!("Execute: {}", x))},
+            _ => (),
+        }
+    }
+    files.retain(|x| simulator.host_path(x).is_file());
+    //Scripts make the same directory once for every file in it.
+    let mut seen : Vec<String> = Vec::new();
+    notes.retain(|x| if seen.contains(x) {false} else {seen.push(x.clone()); true});
+    let closed : bool = simulator.events.iter().any(|x| matches!(x, Event::Closed));
+    notes.push(format!("Close window: {}", if closed {"yes"} else {"no"}));
+
+    let source_of = |target: &str| -> String {
+        let path : PathBuf = simulator.host_path(target);
+        let relative : &Path = path.strip_prefix(&simulator.root).unwrap_or(&path);
+        relative.components().map(|x| x.as_os_str().to_string_lossy().into_owned()).collect::<Vec<String>>().join("/")
+    };
+    let mut manifest : String = format!("# Extracted from {} by 'busbi extract', {} file(s) for a {} target.\n", script_path.display(), files.len(), os_type);
+    let mut remake : String = format!("busbi -o {} --manifest {}", os_type, MANIFEST_NAME);
+    if has_prompts {
+        remake.push_str(" --template");
+    }
+    if let Some(x) = executed {
+        remake.push_str(&format!(" -x {}", source_of(x)));
+    }
+    if closed {
+        remake.push_str(" -c");
+    }
+    manifest.push_str(&format!("# Make the script again from this directory with:\n#   {} {}\n", remake,
+        script_path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default()));
+    for target in &files {
+        //Only an octal chmod is a mode the manifest can give, 'chmod +x' comes from '-x'.
+        let mode : Option<&str> = simulator.events.iter().rev().find_map(|x| match x {
+            Event::SetMode(path, mode) if path == target && u32::from_str_radix(mode, 8).is_ok() => Some(mode.as_str()),
+            _ => None,
+        });
+        match mode {
+            Some(x) => manifest.push_str(&format!("{} = {}; mode={}\n", source_of(target), target, x)),
+            None => manifest.push_str(&format!("{} = {}\n", source_of(target), target)),
+        }
+    }
+    for note in notes {
+        manifest.push_str(&format!("# {}\n", note));
+    }
+    manifest
+}
@@ -1,91 +1,176 @@
+use std::collections::HashMap;
 use std::{env::consts, fs::{File, create_dir_all}};
 use std::io::{self, BufRead, BufWriter, Write};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
-use crate::arguments::parse_directories;
+use crate::arguments::{Arguments, SourceEntry};
 use crate::help_msg::{HelpMessage};
-use crate::boilerplates::{make_file_boilerplate, start_boilerplate, end_boilerplate, executable_boilerplate};
+use crate::metadata::Metadata;
+use crate::patch::{apply_patch, unified_diff};
+use crate::template::collect_prompts;
+use sha2::{Digest, Sha256};
 
-pub fn d_flag_handler(target_os: &str, execute: bool, source_files: Vec<PathBuf>, destination: &str) -> Result<String, HelpMessage> {
-    let mut grande_string : String = String::new();
-    for current_path in source_files {
-        if current_path.is_dir() {
-            let mut list_files : Vec<PathBuf> = Vec::new();
+use crate::boilerplates::{ContentOptions, backed_up_boilerplate, backup_boilerplate, target_file_path, copy_file_boilerplate, delivered_lines, patch_file_boilerplate, prompts_boilerplate, count_keystrokes, make_file_boilerplate, empty_dir_boilerplate, symlink_boilerplate, start_boilerplate, end_boilerplate, executable_boilerplate};
 
-            list_files = match parse_directories(list_files, &current_path) {
-                Ok(x) => x,
-                Err(e) => return Err(e),
-            };
-            match d_flag_handler(target_os, execute, list_files, destination) {
-                Ok(x) => {
+//With 'dedupe' every file content is only typed once, later files with the same content are
+//copied on the target from the first one, as long as the copy command is shorter to type.
+//With '--patch' text files that are in the baseline get patched when that is shorter, see patch.rs.
+pub fn d_flag_handler(args: &Arguments, source_files: Vec<SourceEntry>) -> Result<String, HelpMessage> {
+    let Arguments {target_os, keep_prefix, dedupe, ..} = *args;
+    let destination : &str = args.install_dest();
+    let (source_root, content, baseline) : (&Path, &ContentOptions, Option<&Path>) = (&args.source_path, &args.content, args.patch.as_deref());
+    let mut grande_string : String = String::new();
+    //Hash of every content typed so far, where it was written to and its source file.
+    let mut written : HashMap<String, (PathBuf, PathBuf)> = HashMap::new();
+    let mut patched : bool = false;
+    for entry in source_files {
+        match entry {
+            SourceEntry::File(ref current_path) | SourceEntry::Binary(ref current_path) => {
+                let install_path : PathBuf = install_relative_path(current_path, source_root, keep_prefix);
+                let file_content = make_file_boilerplate(target_os, current_path, &install_path, destination, true, false, content);
+                let baseline_file : Option<PathBuf> = match (baseline, &entry) {
+                    (Some(x), SourceEntry::File(_)) => current_path.strip_prefix(source_root).ok().map(|y| x.join(y)),
+                    _ => None,
+                };
+                if let Some(baseline_file) = baseline_file {
+                    match baseline_patch(target_os, current_path, &install_path, &baseline_file, destination, content, !patched) {
+                        Some(x) if x.is_empty() => {println!("{} is the same as in the baseline, leaving it as it is.", install_path.display()); continue},
+                        Some(x) if count_keystrokes(&x) < count_keystrokes(&file_content) => {
+                            println!("Patching {}: {} keystrokes instead of {}.", install_path.display(), count_keystrokes(&x), count_keystrokes(&file_content));
                             grande_string.push_str(&x);
+                            patched = true;
+                            continue;
                         },
-                Err(e) => {eprintln!("Failed to process files/directories. Got error : {:?}", e); return Err(HelpMessage::FailedRecursionFS)},
-            }
-        } else {
-            let file_content = make_file_boilerplate(target_os, &current_path, destination, true, false);
-            grande_string.push_str(&file_content);
-
+                        _ => (),
+                    }
+                }
+                if dedupe {
+                    let hash : String = match hash_file(current_path) {
+                        Ok(x) => x,
+                        Err(e) => {eprintln!("Could not read {}, got error: {}", current_path.display(), e); return Err(HelpMessage::FailedToGetFile)},
+                    };
+                    if let Some((original, original_source)) = written.get(&hash) {
+                        //'cp' gives the copy the permissions of the original.
+                        let mode : Option<u32> = content.modes.mode(current_path).filter(|x| Some(*x) != content.modes.mode(original_source));
+                        let copy_content = copy_file_boilerplate(target_os, original, &install_path, destination, mode);
+                        if count_keystrokes(&copy_content) < count_keystrokes(&file_content) {
+                            println!("{} has the same content as {}, copying it on the target.", install_path.display(), original.display());
+                            grande_string.push_str(&copy_content);
+                            continue;
+                        }
+                    } else {
+                        written.insert(hash, (install_path, current_path.clone()));
+                    }
+                }
+                grande_string.push_str(&file_content);
+            },
+            SourceEntry::EmptyDir(current_path) => {
+                let install_path : PathBuf = install_relative_path(&current_path, source_root, keep_prefix);
+                grande_string.push_str(&empty_dir_boilerplate(target_os, &install_path, destination));
+            },
+            SourceEntry::Symlink(current_path, link_target) => {
+                let install_path : PathBuf = install_relative_path(&current_path, source_root, keep_prefix);
+                grande_string.push_str(&symlink_boilerplate(target_os, &install_path, &link_target, destination));
+            },
         }
     }
     Ok(grande_string)
 } 
 
-pub fn file_handler(target_os: &str, execute: bool, source_files: Vec<PathBuf>, destination: &str, d_flag: bool, m_flag: bool, close_window : bool) -> Result<(), HelpMessage> {
+//The patch for a file that is in the '--patch' baseline, an empty String if it didn't change and
+//None if it has to be written in full. Every diff is applied to the baseline here first, the way
+//it arrives typed on the target, and only used when that gives the new version.
+fn baseline_patch(target_os: &str, current_path: &Path, install_path: &Path, baseline_file: &Path, destination: &str, content: &ContentOptions, define_function: bool) -> Option<String> {
+    if !matches!(is_text_file(baseline_file), Ok(true)) {
+        return None
+    }
+    //Prompts are filled in on the target, so the typed lines aren't the ones on the target.
+    if content.template.is_some() && !collect_prompts(&[current_path, baseline_file]).is_empty() {
+        println!("{} has prompts, writing it in full.", install_path.display());
+        return None
+    }
+    let (old, new) : (Vec<String>, Vec<String>) = match (delivered_lines(baseline_file, content), delivered_lines(current_path, content)) {
+        (Ok(x), Ok(y)) => (x, y),
+        _ => return None,
+    };
+    if old == new {
+        return Some(String::new())
+    }
+    let name : String = install_path.components().map(|x| x.as_os_str().to_string_lossy().into_owned()).collect::<Vec<String>>().join("/");
+    let diff : Vec<String> = match unified_diff(&old, &new, &name) {
+        Some(x) => x,
+        None => {println!("{} changed too much to patch, writing it in full.", install_path.display()); return None},
+    };
+    let typed : Vec<String> = diff.iter().map(|x| if x.trim().is_empty() {String::new()} else {x.clone()}).collect();
+    if apply_patch(&old, &typed).as_ref() != Some(&new) {
+        println!("The patch for {} doesn't apply cleanly, writing it in full.", install_path.display());
+        return None
+    }
+    //The patched file keeps the permissions the earlier version got.
+    let mode : Option<u32> = match content.modes.chmod_mode(current_path) {
+        None if file_mode(baseline_file) != file_mode(current_path) => file_mode(current_path),
+        x => x,
+    };
+    Some(patch_file_boilerplate(target_os, install_path, destination, &diff, mode, define_function))
+}
 
-    for current_path in source_files {
-        if current_path.is_dir() {
-            let mut list_files : Vec<PathBuf> = Vec::new();
-            list_files = match parse_directories(list_files, &current_path) {
-                Ok(x) => x,
-                Err(e) => return Err(e),
-            };
-            file_handler(target_os, execute, list_files, destination, d_flag, m_flag, close_window)?;
-        } else {
+pub fn file_handler(args: &Arguments, source_files: &[SourceEntry], metadata: &Metadata) -> Result<(), HelpMessage> {
+    let Arguments {target_os, execute, destination, d_flag, m_flag, close_window, ..} = *args;
 
-            let mut my_path : PathBuf = PathBuf::new();
-            println!("current OS is: {}", consts::OS);
-            if consts::OS == "windows" {
-                my_path = PathBuf::from(format!("{}\\{}", destination, &current_path.display()));
-            } else if consts::OS == "linux" || consts::OS == "macos" {
-                my_path = PathBuf::from(format!("{}/{}", destination, &current_path.display()));
-            }
-            let parent_path : &Path = &my_path.parent().unwrap();
-            //Changes the file extension of what it was to '.txt', which the BadUSB format requires.
-            let txt_path : PathBuf = PathBuf::from(PathBuf::from(&my_path.file_name().unwrap()).file_stem().unwrap()).with_extension("txt"); 
-            //Rejoins parent and new file names, after change to file name extension.
-            let joined_path : PathBuf = parent_path.join(&txt_path);
-            let new_file = match file_nested_dirs(&joined_path) {
-                Ok(file) => file,
-                Err(e) => {eprintln!("Got error message: {:?}", e); return Err(e)},
-            };
-            let mut write_buf = BufWriter::with_capacity(1000000, new_file);
+    for entry in source_files {
+        //Every script created here delivers a single file, empty directories and links have no
+        //script of their own.
+        let current_path : &PathBuf = match entry {
+            SourceEntry::File(x) | SourceEntry::Binary(x) => x,
+            _ => continue,
+        };
+        let install_path : PathBuf = install_relative_path(current_path, &args.source_path, args.keep_prefix);
+        let my_path : PathBuf = Path::new(destination).join(&install_path);
+        let parent_path : &Path = my_path.parent().unwrap();
+        //Changes the file extension of what it was to '.txt', which the BadUSB format requires.
+        let txt_path : PathBuf = PathBuf::from(PathBuf::from(&my_path.file_name().unwrap()).file_stem().unwrap()).with_extension("txt"); 
+        //Rejoins parent and new file names, after change to file name extension.
+        let joined_path : PathBuf = parent_path.join(&txt_path);
+        let new_file = match file_nested_dirs(&joined_path) {
+            Ok(file) => file,
+            Err(e) => {eprintln!("Got error message: {:?}", e); return Err(e)},
+        };
+        let mut write_buf = BufWriter::with_capacity(1000000, new_file);
 
-            let start_boiler: String = start_boilerplate(target_os, true, destination );
-            let _ = write_buf.write(start_boiler.as_bytes());
+        let start_boiler: String = start_boilerplate(target_os, true, destination, &metadata.with_sources(&[current_path]));
+        let _ = write_buf.write(start_boiler.as_bytes());
+        if args.content.template.is_some() {
+            let _ = write_buf.write(prompts_boilerplate(target_os, &collect_prompts(&[current_path])).as_bytes());
+        }
 
-            let file_content : String = make_file_boilerplate(target_os, &current_path, destination, false, true);
-            let _ = write_buf.write(file_content.as_bytes());
+        if args.backup {
+            let target : String = target_file_path(target_os, current_path, &install_path, destination, false, true);
+            let _ = write_buf.write(backup_boilerplate(target_os, destination, &[(target, false)]).as_bytes());
+        }
+        let file_content : String = make_file_boilerplate(target_os, current_path, &install_path, destination, false, true, &args.content);
+        let _ = write_buf.write(file_content.as_bytes());
+        if args.backup {
+            let _ = write_buf.write(backed_up_boilerplate(target_os).as_bytes());
+        }
 
-            if execute {
-                // Here I use &current path instead of x_value, I do this because on -m flag it makes no sense
-                // to specify a file name for all of the new files to execute, so instead each file
-                // will execute the file it creates. 
-                let execute_boiler : String = match executable_boilerplate(target_os, &current_path, destination, d_flag, m_flag) {
-                    Ok(x) => x,
-                    Err(e) => panic!("Got an error: {:?}",e),
-                };
-                let _ = write_buf.write(execute_boiler.as_bytes());
-            } else {
-                let end_boiler: String = end_boilerplate(target_os, close_window);
-                let _ = write_buf.write(end_boiler.as_bytes());
-            }
-            //This pushes the contents of the buffer to the file. 
-            match write_buf.flush() {
-                Ok(_x) => println!("Successfully wrote file."),
-                Err(e) => {eprintln!("got error: {}", e); return Err(HelpMessage::BufferFlushFailed)} 
+        if execute {
+            // Here I use &current path instead of x_value, I do this because on -m flag it makes no sense
+            // to specify a file name for all of the new files to execute, so instead each file
+            // will execute the file it creates. 
+            let execute_boiler : String = match executable_boilerplate(target_os, current_path, destination, d_flag, m_flag) {
+                Ok(x) => x,
+                Err(e) => panic!("Got an error: {:?}",e),
             };
+            let _ = write_buf.write(execute_boiler.as_bytes());
+        } else {
+            let end_boiler: String = end_boilerplate(target_os, close_window);
+            let _ = write_buf.write(end_boiler.as_bytes());
         }
+        //This pushes the contents of the buffer to the file. 
+        match write_buf.flush() {
+            Ok(_x) => println!("Successfully wrote file."),
+            Err(e) => {eprintln!("got error: {}", e); return Err(HelpMessage::BufferFlushFailed)} 
+        };
     }
     Ok(())
 }
@@ -105,12 +190,12 @@ fn file_nested_dirs(my_path: &PathBuf) -> Result<File, HelpMessage> {
     }
 }
 //Function to change the \ to / and reverse.
-pub fn adapt_path(the_path: &PathBuf, target_os: &str) -> Result<PathBuf, HelpMessage> {
+pub fn adapt_path(the_path: &Path, target_os: &str) -> Result<PathBuf, HelpMessage> {
     let path_string : &str = match the_path.to_str() {
         Some(x) => x,
         None => return Err(HelpMessage::FailedWorkingPath),
     };
-    let mut modified_path : String = String::new();
+    let mut modified_path : String = path_string.to_string();
     if target_os.to_lowercase() != consts::OS {
         if target_os.to_lowercase() == "windows" {
             modified_path = path_string.replace("/","\\");
@@ -123,6 +208,85 @@ pub fn adapt_path(the_path: &PathBuf, target_os: &str) -> Result<PathBuf, HelpMe
     
 }
 
+//Gives the path a source file should have below the install directory on the target. By default
+//this is the path relative to the SOURCE directory the user gave, so 'scripts_test/hello.sh' from
+//'-d scripts_test/' becomes 'hello.sh'. With '--keep-prefix' the source path is kept as given.
+//Either way only plain path components survive, so '..', '/' or 'C:\' can never lead outside of
+//the install directory.
+pub fn install_relative_path(source_file: &Path, source_root: &Path, keep_prefix: bool) -> PathBuf {
+    let relative : &Path = if keep_prefix {
+        source_file
+    } else {
+        source_file.strip_prefix(source_root).unwrap_or(source_file)
+    };
+    let mut install_path : PathBuf = PathBuf::new();
+    for component in relative.components() {
+        if let Component::Normal(x) = component {
+            install_path.push(x);
+        }
+    }
+    if install_path.as_os_str().is_empty() && let Some(x) = source_file.file_name() {
+        install_path.push(x);
+    }
+    install_path
+}
+
+//Turns a target path given by the user, like '~/.config/nvim/init.lua', into the form the
+//target shell understands. '~' becomes $HOME and relative paths are placed under $HOME.
+pub fn resolve_target_path(target_os: &str, target: &str) -> String {
+    let separator : char = if target_os.to_lowercase() == "windows" {'\\'} else {'/'};
+    let normalised : String = target.replace(['/', '\\'], &separator.to_string());
+    let is_absolute : bool = normalised.starts_with(separator) || normalised.starts_with("$HOME")
+        || normalised.chars().nth(1) == Some(':');
+    if normalised == "~" {
+        "$HOME".to_string()
+    } else if let Some(rest) = normalised.strip_prefix(&format!("~{}", separator)) {
+        format!("$HOME{}{}", separator, rest)
+    } else if is_absolute {
+        normalised
+    } else {
+        format!("$HOME{}{}", separator, normalised)
+    }
+}
+
+//The permission bits of a source file, so they can be recreated on Unix targets. Other host
+//systems don't have them, there the target keeps its defaults.
+#[cfg(unix)]
+pub fn file_mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    match path.metadata() {
+        Ok(x) => Some(x.permissions().mode() & 0o7777),
+        Err(e) => {eprintln!("Could not read permissions of {}, got error: {}", path.display(), e); None},
+    }
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(_path: &Path) -> Option<u32> {
+    None
+}
+
+//What a file created with 'cat >' gets with the usual umask of 022.
+const NEW_FILE_MODE : u32 = 0o644;
+
+//The permission bits of a source file unless they are the 0644 a new file gets on a Unix target
+//anyway, then there is no chmod to type.
+pub fn changed_file_mode(path: &Path) -> Option<u32> {
+    file_mode(path).filter(|x| *x != NEW_FILE_MODE)
+}
+
+//A file counts as text when it has no NUL bytes and is valid UTF-8 all the way through, which is
+//what typing it line by line needs.
+pub fn is_text_file(path: &Path) -> io::Result<bool> {
+    let contents : Vec<u8> = std::fs::read(path)?;
+    Ok(!contents.contains(&0) && std::str::from_utf8(&contents).is_ok())
+}
+
+//SHA-256 of the file content as hex.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let contents : Vec<u8> = std::fs::read(path)?;
+    Ok(Sha256::digest(&contents).iter().map(|x| format!("{:02x}", x)).collect())
+}
+
 pub fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
     where P: AsRef<Path>, {
     
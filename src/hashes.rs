@@ -0,0 +1,139 @@
+use std::fs::{read, write};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::archive::file_contents;
+use crate::arguments::SourceEntry;
+use crate::boilerplates::ContentOptions;
+use crate::crypto::{decrypt_openssl, encrypt_openssl, existing_passphrase, is_encrypted};
+use crate::handlers::install_relative_path;
+use crate::help_msg::HelpMessage;
+
+// Build manifests for incremental updates. Every '-d' build writes the SHA-256 of every file it
+// delivers next to the script, 'payload.busbi-hashes' for 'payload.txt', in the format sha256sum
+// uses. With '--since' and the manifest of an earlier build, the script only types the files that
+// are new or changed and deletes the ones that are gone, instead of the whole directory. With
+// '--encrypt' the file is encrypted with the passphrase of the payload, since the names and hashes
+// would give its content away.
+
+const HASHES_EXTENSION : &str = "busbi-hashes";
+
+//Install path with '/' separators and the SHA-256 of the content as hex.
+pub type FileHash = (String, String);
+
+pub fn hashes_path(destination: &str) -> PathBuf {
+    Path::new(destination).with_extension(HASHES_EXTENSION)
+}
+
+fn install_key(path: &Path, source_root: &Path, keep_prefix: bool) -> String {
+    install_relative_path(path, source_root, keep_prefix).components()
+        .map(|x| x.as_os_str().to_string_lossy().into_owned()).collect::<Vec<String>>().join("/")
+}
+
+fn is_install_key(path: &str) -> bool {
+    !path.is_empty() && !path.contains('\\') && install_key(Path::new(path), Path::new(""), false) == path
+}
+
+//Hashes what gets delivered rather than the source, so a different '--var' counts as a change too.
+pub fn hash_entries(entries: &[SourceEntry], source_root: &Path, keep_prefix: bool, content: &ContentOptions) -> io::Result<Vec<FileHash>> {
+    let mut hashes : Vec<FileHash> = Vec::new();
+    for entry in entries {
+        if let SourceEntry::File(path) | SourceEntry::Binary(path) = entry {
+            let digest : String = Sha256::digest(file_contents(path, content)?).iter().map(|x| format!("{:02x}", x)).collect();
+            hashes.push((install_key(path, source_root, keep_prefix), digest));
+        }
+    }
+    Ok(hashes)
+}
+
+pub fn write_hashes(hashes_path: &Path, hashes: &[FileHash], passphrase: Option<&str>) -> io::Result<()> {
+    let contents : String = hashes.iter().map(|(path, hash)| format!("{}  {}\n", hash, path)).collect();
+    match passphrase {
+        Some(x) => write(hashes_path, encrypt_openssl(contents.as_bytes(), x).map_err(|e| io::Error::other(format!("{:?}", e)))?),
+        None => write(hashes_path, contents),
+    }
+}
+
+//An encrypted file is decrypted with 'passphrase', or with the one from BUSBI_PASSPHRASE or asked
+//for when this build isn't encrypted.
+pub fn read_hashes(hashes_path: &Path, passphrase: Option<&str>) -> Result<Vec<FileHash>, HelpMessage> {
+    let mut data : Vec<u8> = match read(hashes_path) {
+        Ok(x) => x,
+        Err(e) => {println!("Could not read '{}', got error: {}", hashes_path.display(), e); return Err(HelpMessage::FailedReadingHashes)},
+    };
+    if is_encrypted(&data) {
+        let passphrase : String = match passphrase {
+            Some(x) => x.to_string(),
+            None => existing_passphrase()?,
+        };
+        data = match decrypt_openssl(&data, &passphrase) {
+            Some(x) => x,
+            None => {println!("Could not decrypt '{}', is the passphrase the same as for that build?", hashes_path.display()); return Err(HelpMessage::FailedReadingHashes)},
+        };
+    }
+    let contents : String = match String::from_utf8(data) {
+        Ok(x) => x,
+        Err(_e) => {println!("Error: '{}' is not a hashes file.", hashes_path.display()); return Err(HelpMessage::FailedReadingHashes)},
+    };
+    let mut hashes : Vec<FileHash> = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        match line.split_once("  ") {
+            //Files that are gone since get deleted on the target, so only plain paths inside of the
+            //install directory are taken, like install_relative_path() gives them.
+            Some((hash, path)) if hash.len() == 64 && !is_install_key(path) => {
+                println!("Error: line {} of '{}' has '{}', which is not a path inside of the install directory.", number + 1, hashes_path.display(), path);
+                return Err(HelpMessage::FailedReadingHashes)
+            },
+            Some((hash, path)) if hash.len() == 64 => hashes.push((path.to_string(), hash.to_string())),
+            _ if line.trim().is_empty() => (),
+            _ => {println!("Error: line {} of '{}' is not 'hash  path'.", number + 1, hashes_path.display()); return Err(HelpMessage::FailedReadingHashes)},
+        }
+    }
+    Ok(hashes)
+}
+
+//The entries that still have to be delivered and the install paths of the files that were removed
+//since the earlier build. Empty directories and links are always kept, recreating them is cheap.
+pub fn changes_since(entries: &[SourceEntry], hashes: &[FileHash], earlier: &[FileHash], source_root: &Path, keep_prefix: bool) -> (Vec<SourceEntry>, Vec<String>) {
+    let changed : Vec<SourceEntry> = entries.iter().filter(|entry| match entry {
+        SourceEntry::File(path) | SourceEntry::Binary(path) => {
+            let key : String = install_key(path, source_root, keep_prefix);
+            let hash : Option<&FileHash> = hashes.iter().find(|x| x.0 == key);
+            !earlier.iter().any(|x| Some(x) == hash)
+        },
+        _ => true,
+    }).cloned().collect();
+    let removed : Vec<String> = earlier.iter().filter(|x| !hashes.iter().any(|y| y.0 == x.0)).map(|x| x.0.clone()).collect();
+    (changed, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::remove_file;
+    use std::process;
+
+    #[test]
+    fn only_plain_relative_paths_are_install_keys() {
+        for path in ["a.txt", "sub/dir/a.txt", ".config/x", "..a"] {
+            assert!(is_install_key(path), "{}", path);
+        }
+        for path in ["", "../a.txt", "sub/../../a", "/etc/passwd", "./a.txt", "a//b", "..\\a.txt", "C:\\x"] {
+            assert!(!is_install_key(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn hashes_with_paths_outside_of_the_install_directory_are_rejected() {
+        let file : PathBuf = env::temp_dir().join(format!("busbi-test-hashes-{}", process::id()));
+        let hash : String = "0".repeat(64);
+        write(&file, format!("{}  a.txt\n{}  sub/b.txt\n", hash, hash)).unwrap();
+        assert_eq!(read_hashes(&file, None).unwrap().len(), 2);
+        write(&file, format!("{}  a.txt\n{}  ../../.bashrc\n", hash, hash)).unwrap();
+        assert!(read_hashes(&file, None).is_err());
+        remove_file(&file).unwrap();
+    }
+}
@@ -15,13 +15,46 @@ pub enum HelpMessage {
     NoParentPath,
     FailedMakingDirs,
     FailedWorkingPath,
-    FailedRecursionFS,
+    NoValueForFlagMap,
+    NoValueForFlagManifest,
+    InvalidMapping,
+    FailedReadingManifest,
+    NoValueForFlagSymlinks,
+    WrongArgSymlinks,
+    NoValueForFlagInclude,
+    NoValueForFlagExclude,
+    NoValueForFlagOrder,
+    WrongArgOrder,
+    NoValueForFlagBinary,
+    WrongArgBinary,
+    NoValueForFlagMaxSize,
+    WrongArgSize,
+    BinaryFile,
+    NoValueForFlagVar,
+    NoValueForFlagVars,
+    InvalidVar,
+    FailedReadingVars,
+    UndefinedVariable,
+    NoValueForFlagSecrets,
+    WrongArgSecrets,
+    NoValueForFlagAllowlist,
+    FailedReadingAllowlist,
+    SecretsFound,
+    NoPassphrase,
+    EncryptionFailed,
+    NoValueForFlagSince,
+    NoValueForFlagInstallDir,
+    InvalidInstallDir,
+    FailedReadingHashes,
+    NoValueForFlagPatch,
+    NoValueForFlagMode,
+    InvalidModeOverride,
     PrintingHelp,
 }
 
 pub fn help_message()  {
 
-    println!("");
+    println!();
     println!("\t\t\t\x1b[4;32;40;1mWELCOME TO BUSBI!\x1b[0m\n");
     println!(" This Command Line Tool is for creating bad USB scripts AKA ducky scripts for copying");
     println!(" over targeted scripts or entire folders of them onto another machine through a ducky script");
@@ -30,10 +63,39 @@ pub fn help_message()  {
     println!(" file works. So you can copy code or whole essays this way.\n");
     println!(" The files generated by busbi use the Flipper Zero BadUSB File format, see their webpage:\n");
     println!(" https://developer.flipper.net/flipperzero/doxygen/badusb_file_format.html");
-    println!("");
+    println!();
     print!(" ");
     print!("\x1b[4;31mUSAGE:\x1b[0m");
-    println!(" busbi [Options] [SOURCE FILE/DIR] [DESTINATION FILE/DIR]\n");
+    println!(" busbi [Options] [SOURCE FILE/DIR] [DESTINATION FILE/DIR]");
+    println!("        busbi [COMMAND] [ARGUMENTS]\n");
+    print!(" ");
+    println!("\x1b[4;35mCommands:\x1b[0m\n");
+    println!("\tlint <file.txt>  Checks a bad USB script against the Flipper BadUSB format. Reports\n\
+              \t                 unknown commands and keys, malformed lines, over-long STRING lines\n\
+              \t                 and missing delays after launchers, with line numbers. Exits with\n\
+              \t                 1 if there are errors.\n");
+    println!("\tsimulate <file.txt> [--os windows|unix] [--source PATH] [--out DIR]\n\
+              \t                 Replays a bad USB script like the shell on the target would and\n\
+              \t                 writes the files it creates to a temporary directory, or to '--out'.\n\
+              \t                 With '--source' they are compared with the files the script was\n\
+              \t                 made from, after the ignore rules, '--minify' and templates of\n\
+              \t                 its build. Builds with '--var' can't be compared, the values are\n\
+              \t                 not in the script. Exits with 1 if the script fails or files differ.\n");
+    println!("\textract <file.txt> [--os windows|unix] [--out DIR]\n\
+              \t                 Gets the files back out of a bad USB script busbi made, below DIR\n\
+              \t                 under their path on the target. DIR defaults to '<file>_extracted'.\n\
+              \t                 Writes a busbi_manifest.txt that '--manifest' can read, with the\n\
+              \t                 execute and close steps as comments.\n");
+    println!("\texplain <file.txt> [--os windows|unix] [--format text|json]\n\
+              \t                 Says what a bad USB script will do on the target, for a review\n\
+              \t                 before it runs: the launcher, directories and files created with\n\
+              \t                 their size, execution policy changes, what gets executed and\n\
+              \t                 whether the window closes.\n");
+    println!("\tinspect <file.txt>\n\
+              \t                 Reads the header busbi puts at the top of every script: the busbi\n\
+              \t                 version, when it was built, the target OS, the options used and a\n\
+              \t                 SHA-256 of every source file, checked against the local files.\n\
+              \t                 '--encrypt' payloads only say how many source files they have.\n");
     print!(" ");
     println!("\x1b[4;35mPossible Options:\x1b[0m\n");
     println!("\t-o  --os         Selects the OS that you are targeting, i.e where your Bad USB\n\
@@ -51,7 +113,10 @@ pub fn help_message()  {
               \t                 When the bad USB script is used on the target system it will\n\
               \t                 replicate the folders and files in their entirety on the host\n\
               \t                 system under the $HOME\\DESTINATION directory or $HOME/DESTINATION\n\
-              \t                 if on Unix.\n\
+              \t                 if on Unix. Paths are relative to the SOURCE directory, so\n\
+              \t                 'SOURCE/a/b.sh' ends up as 'DESTINATION/a/b.sh'.\n\
+              \t                 A second script, DESTINATION_uninstall.txt, removes everything\n\
+              \t                 again, restores '--backup' backups and the execution policy.\n\
               \t                 See the Examples below for more help.\n
         ");
     println!("\t-m  --many       Use this flag to target a directory containing many files and/or\n\
@@ -69,6 +134,114 @@ pub fn help_message()  {
     print!("\x1b[4mNote:\x1b[0m");
     print!(" If used with '-x' option, this will cause all generated bad\n\
               \t                 USB scripts to execute the file they copy over onto the host.\n\n");
+    println!("\t    --map        Maps a single source file to the exact path it should have on the\n\
+              \t                 target, written as 'SOURCE=TARGET', for example\n\
+              \t                 'dotfiles/bashrc=~/.bashrc'. Can be repeated. A TARGET starting with\n\
+              \t                 '~' or a relative TARGET is placed under $HOME. Parent directories\n\
+              \t                 are created on the target. When mapping files only the DESTINATION\n\
+              \t                 (the bad USB script) is given as argument.\n");
+    println!("\t    --manifest   Reads mappings from a file, one 'SOURCE = TARGET' per line. Lines\n\
+              \t                 starting with '#' are ignored. Relative SOURCE paths are relative\n\
+              \t                 to the manifest. Can be combined with '--map'.\n\
+              \t                 On Unix targets every file gets the permissions its source file\n\
+              \t                 has, a chmod is only typed for other modes than 0644. Add\n\
+              \t                 '; mode=0600' after a mapping (or ';mode=0600' to a '--map'\n\
+              \t                 value) to set other permissions for that file.\n");
+    println!("\t    --mode       Sets the permissions of the files created with '-d' or '-m' that\n\
+              \t                 match a glob, written as 'GLOB=MODE', for example 'bin/*.sh=0755'\n\
+              \t                 or 'ssh/config=0600'. Globs work like in a .gitignore file and are\n\
+              \t                 relative to SOURCE. Can be repeated, the last match wins. Must come\n\
+              \t                 before '-d' or '-m'.\n");
+    println!("\t    --keep-prefix Keeps the SOURCE path as given in front of every file created with\n\
+              \t                 '-d' or '-m', instead of making paths relative to SOURCE. Any\n\
+              \t                 '..' or leading '/' is still dropped so files never end up outside\n\
+              \t                 of DESTINATION. Must come before '-d' or '-m'.\n");
+    println!("\t    --symlinks   What to do with symlinks inside a '-d' or '-m' directory: 'link'\n\
+              \t                 recreates them as links on Unix targets, 'copy' copies what they\n\
+              \t                 point to and 'skip' leaves them out. Windows targets and '-m' always\n\
+              \t                 copy instead of linking. Must come before '-d' or '-m'.");
+    print!("\t                 ");
+    println!("\x1b[4mDefault: link\x1b[0m\n");
+    println!("\t    --exclude    Leaves files and directories matching the given glob out of '-d'\n\
+              \t                 and '-m', for example '*.swp' or 'target/'. Can be repeated.\n\
+              \t                 Globs work like in a .gitignore file. A '.busbiignore' file in the\n\
+              \t                 directory or any sub-directory is always read the same way.\n");
+    println!("\t    --include    Only keeps files matching the given glob. Can be repeated.\n");
+    println!("\t    --gitignore  Also leaves out whatever '.gitignore' files ignore, and '.git'.\n");
+    println!("\t    --order      Order in which '-d' and '-m' go through every directory: 'name',\n\
+              \t                 'dirs-first' or 'files-first'. Entries are always sorted by name\n\
+              \t                 so the same directory always gives exactly the same script.");
+    print!("\t                 ");
+    println!("\x1b[4mDefault: name\x1b[0m\n");
+    println!("\t    --binary     What to do with files that aren't text: 'skip' leaves them out,\n\
+              \t                 'encode' types them as base64 and decodes them on the target and\n\
+              \t                 'fail' stops without creating anything.");
+    print!("\t                 ");
+    println!("\x1b[4mDefault: skip\x1b[0m\n");
+    println!("\t    --max-file-size  Leaves out files bigger than this, like '512', '100K' or '2M'.\n\
+              \t                 'none' turns the limit off.");
+    print!("\t                 ");
+    println!("\x1b[4mDefault: 1M\x1b[0m\n");
+    println!("\t    --max-total-size Leaves out the remaining files once the delivered files add up\n\
+              \t                 to this size. 'none' turns the limit off.");
+    print!("\t                 ");
+    println!("\x1b[4mDefault: 10M\x1b[0m\n");
+    println!("\t-z  --compress   Packs the '-d' directory into a compressed archive and types that\n\
+              \t                 as base64 instead of every line of every file, which is a lot\n\
+              \t                 faster for bigger directories. Unix targets unpack it with\n\
+              \t                 'base64 -d | tar xz', Windows targets with Expand-Archive. Busbi\n\
+              \t                 prints how many keystrokes this saves.\n");
+    println!("\t    --encrypt    Encrypts the '-z' archive with a passphrase, so the files are never\n\
+              \t                 stored in plain text on the Flipper. The passphrase is read from\n\
+              \t                 BUSBI_PASSPHRASE or asked for, and the script asks the operator\n\
+              \t                 for it on the target. Unix targets need 'openssl'. The hashes\n\
+              \t                 file and the uninstall script are encrypted too. Implies '-z'.\n");
+    println!("\t    --no-dedupe  With '-d' files with the same content are only typed once and\n\
+              \t                 copied on the target for every other place they appear in. This\n\
+              \t                 types every copy in full instead.\n");
+    println!("\t    --since      Every '-d' build writes the SHA-256 of each file it delivers to\n\
+              \t                 '<name>.busbi-hashes' next to the script. Give that file from an\n\
+              \t                 earlier build and the script only types the files that are new or\n\
+              \t                 changed since then, and deletes the ones that are gone.\n");
+    println!("\t    --install-dir  Names the install directory on the target for '-d', which is\n\
+              \t                 named after the script otherwise. Give an update built with\n\
+              \t                 '--since' or '--patch' the name of the first build's directory.\n");
+    println!("\t    --patch      Takes a copy of the source directory as it was delivered before.\n\
+              \t                 Unchanged files are left out. Text files that changed since are\n\
+              \t                 typed as a diff and patched on the target, with 'patch' on Unix\n\
+              \t                 and a PowerShell function on Windows, when the diff is shorter.\n\
+              \t                 A file that isn't the baseline version on the target is left as\n\
+              \t                 it is, with an error. Can't be used with '-z'.\n");
+    println!("\t    --backup     Moves every file the script is about to overwrite or remove into\n\
+              \t                 $HOME/.busbi_backup/<name>-<date>-<time> on the target first, and\n\
+              \t                 prints what was backed up once everything is written. Files that\n\
+              \t                 get patched are copied instead.\n");
+    println!("\t    --minify     Leaves out comment lines and blank lines of shell, PowerShell,\n\
+              \t                 Python and INI/TOML files before typing them. Shebangs and\n\
+              \t                 '#requires' lines are kept, and so are here-documents and\n\
+              \t                 here-strings. Files packed with '-z' are minified too.\n");
+    println!("\t    --var        Sets a template variable, like 'proxy_host=10.0.0.1'. Every\n\
+              \t                 '{{{{ proxy_host }}}}' in the text files is replaced with its value\n\
+              \t                 before typing. Can be given more than once.\n");
+    println!("\t    --vars       Reads template variables from a file with one 'name = value' per\n\
+              \t                 line. '--var' wins over the file, the file wins over environment\n\
+              \t                 variables.\n");
+    println!("\t    --template   Fills in placeholders from environment variables only. A\n\
+              \t                 placeholder without a value stops busbi, write '\\{{{{' for literal\n\
+              \t                 braces. '{{{{ prompt:name }}}}' is asked for on the target instead,\n\
+              \t                 without echoing, and the Flipper waits for its button after each\n\
+              \t                 question. Can't be used with '-z'.\n");
+    println!("\t    --secrets    Scans the files for private keys, AWS keys, random looking tokens\n\
+              \t                 and .env files before anything is created. 'fail' stops busbi,\n\
+              \t                 'warn' only prints what was found and 'off' doesn't scan. Lines\n\
+              \t                 containing 'busbi:allow' are never reported.");
+    print!("\t                 ");
+    println!("\x1b[4mDefault: fail\x1b[0m\n");
+    println!("\t    --secrets-allowlist  File with one 'path' or 'path:line' per line that the\n\
+              \t                 scan leaves alone. Paths can use globs like '.busbiignore'\n\
+              \t                 and may be relative to the '-d' directory.\n");
+    println!("\t    --list       Prints the files '-d' or '-m' would deliver, after all ignore\n\
+              \t                 rules, and stops without creating anything.\n");
     println!("\t-c  --close      Closes the terminal/powershell window when the bad USB script\n\
               \t                 finishes.\n");
     println!("\t-x  --execute    Use this option to specify 1 file to execute at the end of the\n\
@@ -87,7 +260,7 @@ pub fn help_message()  {
               \t                 copied over, as long as they are in the $HOME/DESTINAITON folder.\n");
     print!(" ");
     println!("\x1b[4;32mExamples:\x1b[0m");
-    println!("");
+    println!();
     println!("\tAll examples will assume the existence of these files and folder:\n");
     println!("\t\tscripts_test/
 \t\t├── hello.sh
@@ -153,4 +326,12 @@ pub fn help_message()  {
 \t\t    │   └── count.sh
 \t\t    └── whoami.sh
 ");
+    println!("\t\x1b[4mTarget Unix and deliver dotfiles to their exact paths:\x1b[0m\n");
+    println!("\t\tbusbi -o unix --map dotfiles/bashrc=~/.bashrc --map nvim/init.lua=~/.config/nvim/init.lua dotfiles.txt\n");
+    println!("\tOr with a manifest file containing the same mappings:");
+    println!("\t\t# dotfiles.manifest
+\t\tdotfiles/bashrc = ~/.bashrc
+\t\tnvim/init.lua = ~/.config/nvim/init.lua
+");
+    println!("\t\tbusbi -o unix --manifest dotfiles.manifest dotfiles.txt\n");
 }
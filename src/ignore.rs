@@ -0,0 +1,285 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+// Ignore rules for walking source directories. The syntax is the one of .gitignore files: '*',
+// '?', '[a-z]' and '**', a leading '!' un-ignores, a trailing '/' only matches directories and a
+// pattern containing a '/' is relative to the directory the rules came from instead of matching
+// the file name at any depth.
+
+pub const BUSBI_IGNORE : &str = ".busbiignore";
+pub const GIT_IGNORE : &str = ".gitignore";
+
+#[derive(Debug, Clone)]
+pub struct IgnoreRule {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+//The rules of one ignore file, or of the '--include'/'--exclude' flags, together with the
+//directory (relative to the source root) they apply to.
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    base: String,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRule {
+    pub fn parse(line: &str) -> Option<IgnoreRule> {
+        let mut pattern : &str = line.trim_end();
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return None
+        }
+        let negated : bool = pattern.starts_with('!');
+        //Drops the '!' or the backslash of an escaped '\!' or '\#'.
+        if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern = &pattern[1..];
+        }
+        let dir_only : bool = pattern.ends_with('/');
+        let pattern : &str = pattern.trim_end_matches('/');
+        let anchored : bool = pattern.contains('/');
+        let pattern : &str = pattern.trim_start_matches('/');
+        if pattern.is_empty() {
+            return None
+        }
+        Some(IgnoreRule { pattern: pattern.to_string(), negated, dir_only, anchored })
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    //'relative_path' uses '/' as separator and is relative to the directory of the rule.
+    pub fn matches(&self, relative_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false
+        }
+        if self.anchored {
+            glob_match(&self.pattern, relative_path)
+        } else {
+            let file_name : &str = relative_path.rsplit('/').next().unwrap_or(relative_path);
+            glob_match(&self.pattern, file_name)
+        }
+    }
+}
+
+impl IgnoreRules {
+    pub fn new(base: &str, lines: &[String]) -> IgnoreRules {
+        IgnoreRules { base: base.to_string(), rules: lines.iter().filter_map(|x| IgnoreRule::parse(x)).collect() }
+    }
+
+    //Reads an ignore file, a missing file just gives no rules.
+    pub fn from_file(base: &str, file_path: &Path) -> Option<IgnoreRules> {
+        let contents : String = read_to_string(file_path).ok()?;
+        let lines : Vec<String> = contents.lines().map(|x| x.to_string()).collect();
+        Some(IgnoreRules::new(base, &lines))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    //Gives Some(true) if the last matching rule ignores the path, Some(false) if the last
+    //matching rule un-ignores it and None if no rule matches at all.
+    pub fn decide(&self, relative_path: &str, is_dir: bool) -> Option<bool> {
+        let local_path : &str = if self.base.is_empty() {
+            relative_path
+        } else {
+            relative_path.strip_prefix(&self.base)?.strip_prefix('/')?
+        };
+        self.rules.iter().rev().find(|x| x.matches(local_path, is_dir)).map(|x| !x.negated)
+    }
+}
+
+//Gives the path relative to the source root with '/' as separator, which is what the rules match.
+pub fn relative_rule_path(path: &Path, root: &Path) -> String {
+    let relative : &Path = path.strip_prefix(root).unwrap_or(path);
+    let parts : Vec<String> = relative.components().map(|x| x.as_os_str().to_string_lossy().into_owned()).collect();
+    parts.join("/")
+}
+
+//Checks the ignore files found so far, outermost first so the rules closest to the file win.
+pub fn is_ignored(rule_sets: &[IgnoreRules], relative_path: &str, is_dir: bool) -> bool {
+    let mut ignored : bool = false;
+    for rules in rule_sets {
+        if let Some(x) = rules.decide(relative_path, is_dir) {
+            ignored = x;
+        }
+    }
+    ignored
+}
+
+//Loads the ignore files that live in the given directory.
+pub fn load_ignore_files(directory: &Path, root: &Path, use_gitignore: bool) -> Vec<IgnoreRules> {
+    let base : String = relative_rule_path(directory, root);
+    let mut rule_sets : Vec<IgnoreRules> = Vec::new();
+    let mut names : Vec<&str> = Vec::new();
+    if use_gitignore {
+        names.push(GIT_IGNORE);
+    }
+    //.busbiignore comes last so it can override what .gitignore says.
+    names.push(BUSBI_IGNORE);
+    for name in names {
+        let file_path : PathBuf = directory.join(name);
+        if let Some(rules) = IgnoreRules::from_file(&base, &file_path) {
+            rule_sets.push(rules);
+        }
+    }
+    rule_sets
+}
+
+//Matches a whole path against a glob. '*' and '?' never match a '/', '**' matches any number of
+//directories.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern : Vec<char> = pattern.chars().collect();
+    let text : Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    if pattern.is_empty() {
+        return text.is_empty()
+    }
+    if pattern.starts_with(&['*', '*']) {
+        let rest : &[char] = &pattern[2..];
+        if let Some(after_slash) = rest.strip_prefix(&['/']) {
+            //'**/' matches no directory at all or everything up to and including some '/'.
+            if match_from(after_slash, text) {
+                return true
+            }
+            return (0..text.len()).any(|i| text[i] == '/' && match_from(after_slash, &text[i + 1..]))
+        }
+        return (0..=text.len()).any(|i| match_from(rest, &text[i..]))
+    }
+    match pattern[0] {
+        '*' => {
+            for i in 0..=text.len() {
+                if i > 0 && text[i - 1] == '/' {
+                    break;
+                }
+                if match_from(&pattern[1..], &text[i..]) {
+                    return true
+                }
+            }
+            false
+        },
+        '?' => !text.is_empty() && text[0] != '/' && match_from(&pattern[1..], &text[1..]),
+        '[' => match match_class(&pattern[1..], text.first()) {
+            Some((true, rest)) => match_from(rest, &text[1..]),
+            Some((false, _rest)) => false,
+            //No closing ']', so the '[' is just a character.
+            None => text.first() == Some(&'[') && match_from(&pattern[1..], &text[1..]),
+        },
+        '\\' if pattern.len() > 1 => text.first() == Some(&pattern[1]) && match_from(&pattern[2..], &text[1..]),
+        c => text.first() == Some(&c) && match_from(&pattern[1..], &text[1..]),
+    }
+}
+
+//Matches a character class like '[a-z]' or '[!0-9]', 'class' starts right after the '['. Gives
+//whether it matched and the rest of the pattern after the ']'.
+fn match_class<'a>(class: &'a [char], c: Option<&char>) -> Option<(bool, &'a [char])> {
+    let negated : bool = matches!(class.first(), Some('!') | Some('^'));
+    let start : usize = if negated {1} else {0};
+    //A ']' right at the start is part of the class.
+    let end : usize = start + 1 + class.get(start + 1..)?.iter().position(|x| *x == ']')?;
+    let members : &[char] = &class[start..end];
+    let c : char = match c {
+        Some(x) if *x != '/' => *x,
+        _ => return Some((false, &class[end + 1..])),
+    };
+    let mut found : bool = false;
+    let mut i : usize = 0;
+    while i < members.len() {
+        if i + 2 < members.len() && members[i + 1] == '-' {
+            if members[i] <= c && c <= members[i + 2] {
+                found = true;
+            }
+            i += 3;
+        } else {
+            if members[i] == c {
+                found = true;
+            }
+            i += 1;
+        }
+    }
+    Some((found != negated, &class[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(lines: &[&str]) -> IgnoreRules {
+        IgnoreRules::new("", &lines.iter().map(|x| x.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn double_star_matches_any_number_of_directories() {
+        assert!(glob_match("**/x.sh", "x.sh"));
+        assert!(glob_match("**/x.sh", "a/b/x.sh"));
+        assert!(glob_match("a/**/x.sh", "a/x.sh"));
+        assert!(glob_match("a/**/x.sh", "a/b/c/x.sh"));
+        assert!(glob_match("a/**", "a/b/c"));
+        assert!(!glob_match("a/**/x.sh", "b/a/x.sh"));
+        assert!(!glob_match("a/**/x.sh", "a/b/y.sh"));
+    }
+
+    #[test]
+    fn single_star_and_question_mark_stop_at_slashes() {
+        assert!(glob_match("*.sh", "run.sh"));
+        assert!(!glob_match("*.sh", "bin/run.sh"));
+        assert!(glob_match("?.sh", "a.sh"));
+        assert!(!glob_match("?", "/"));
+        assert!(glob_match("[a-c]x", "bx"));
+        assert!(!glob_match("[!a-c]x", "bx"));
+        assert!(glob_match("[x", "[x"));
+    }
+
+    #[test]
+    fn unanchored_rules_match_the_file_name_at_any_depth() {
+        let rule : IgnoreRule = IgnoreRule::parse("*.swp").unwrap();
+        assert!(rule.matches("a.swp", false));
+        assert!(rule.matches("deep/down/a.swp", false));
+        assert!(!rule.matches("a.swp.txt", false));
+    }
+
+    #[test]
+    fn rules_with_a_slash_are_anchored() {
+        let rule : IgnoreRule = IgnoreRule::parse("build/out").unwrap();
+        assert!(rule.matches("build/out", false));
+        assert!(!rule.matches("src/build/out", false));
+        //A leading '/' only anchors.
+        let rule : IgnoreRule = IgnoreRule::parse("/top.txt").unwrap();
+        assert!(rule.matches("top.txt", false));
+        assert!(!rule.matches("sub/top.txt", false));
+    }
+
+    #[test]
+    fn trailing_slash_only_matches_directories() {
+        let rule : IgnoreRule = IgnoreRule::parse("target/").unwrap();
+        assert!(rule.matches("target", true));
+        assert!(rule.matches("sub/target", true));
+        assert!(!rule.matches("target", false));
+    }
+
+    #[test]
+    fn negation_un_ignores_and_the_last_match_wins() {
+        let set : IgnoreRules = rules(&["*.log", "!keep.log"]);
+        assert_eq!(set.decide("a.log", false), Some(true));
+        assert_eq!(set.decide("keep.log", false), Some(false));
+        assert_eq!(set.decide("a.txt", false), None);
+        let set : IgnoreRules = rules(&["!keep.log", "*.log"]);
+        assert_eq!(set.decide("keep.log", false), Some(true));
+        //An escaped '!' is a file name.
+        assert_eq!(rules(&["\\!x"]).decide("!x", false), Some(true));
+    }
+
+    #[test]
+    fn rules_from_a_sub_directory_only_apply_below_it() {
+        let set : IgnoreRules = IgnoreRules::new("sub", &["/x.txt".to_string()]);
+        assert_eq!(set.decide("sub/x.txt", false), Some(true));
+        assert_eq!(set.decide("x.txt", false), None);
+        assert!(is_ignored(&[rules(&["*.txt"]), set], "sub/y.txt", false));
+    }
+}
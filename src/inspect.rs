@@ -0,0 +1,43 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::handlers::hash_file;
+use crate::metadata::Metadata;
+
+// 'busbi inspect <file.txt>' reads back the header busbi puts at the top of every script, see
+// metadata.rs. Source files that still exist at the same path are checked against their hash, so
+// it shows whether the payload has the current version of each file.
+
+//Runs 'busbi inspect' and gives the exit code, 1 if the script has no header.
+pub fn inspect_command(args: &[String]) -> i32 {
+    let script_path : &Path = match args.first() {
+        Some(x) => Path::new(x),
+        None => {println!("Error: give the bad USB script to inspect, like 'busbi inspect script.txt'."); return 2},
+    };
+    let script : String = match read_to_string(script_path) {
+        Ok(x) => x,
+        Err(e) => {println!("Could not read {}, got error: {}", script_path.display(), e); return 2},
+    };
+    let metadata : Metadata = match Metadata::parse(&script) {
+        Some(x) => x,
+        None => {println!("{} has no busbi header, it was made by hand or by an older busbi.", script_path.display()); return 1},
+    };
+    println!("Made by busbi {} on {}", metadata.version, metadata.built);
+    println!("Target: {}", metadata.target_os);
+    println!("Options: {}", metadata.options);
+    if metadata.hidden_sources > 0 {
+        println!("Source files ({}): not listed, the payload is encrypted.", metadata.hidden_sources);
+        return 0
+    }
+    println!("Source files ({}):", metadata.sources.len());
+    for (path, hash) in &metadata.sources {
+        //Paths are as given to busbi, so relative ones only match when run from the same directory.
+        let status : &str = match hash_file(Path::new(path)) {
+            Ok(x) if x == *hash => "same as the local file",
+            Ok(_x) => "\x1b[33mlocal file has changed\x1b[0m",
+            Err(_e) => "not found here",
+        };
+        println!("  {}  {}  ({})", hash, path, status);
+    }
+    0
+}
@@ -0,0 +1,104 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::ducky::{Command, parse_line};
+
+// 'busbi lint <file.txt>' checks a bad USB script against the Flipper BadUSB format, for generated
+// scripts that were edited by hand as well as scripts written from scratch. Errors are lines the
+// Flipper would refuse or mistype, warnings are lines that tend to break on real machines.
+
+//Longer STRING lines are known to lose characters on slow targets.
+const MAX_STRING_LENGTH : usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+pub struct Problem {
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+//Runs 'busbi lint' and gives the exit code, 1 if there was any error.
+pub fn lint_command(args: &[String]) -> i32 {
+    let script_path : &Path = match args.first() {
+        Some(x) => Path::new(x),
+        None => {println!("Error: give the bad USB script to check, like 'busbi lint script.txt'."); return 2},
+    };
+    let script : String = match read_to_string(script_path) {
+        Ok(x) => x,
+        Err(e) => {println!("Could not read {}, got error: {}", script_path.display(), e); return 2},
+    };
+    let problems : Vec<Problem> = lint_script(&script);
+    for problem in &problems {
+        let label : &str = match problem.severity {
+            Severity::Error => "\x1b[31merror\x1b[0m",
+            Severity::Warning => "\x1b[33mwarning\x1b[0m",
+        };
+        println!("{}:{}: {}: {}", script_path.display(), problem.line, label, problem.message);
+    }
+    let errors : usize = problems.iter().filter(|x| x.severity == Severity::Error).count();
+    let warnings : usize = problems.len() - errors;
+    println!("{} error(s), {} warning(s).", errors, warnings);
+    if errors > 0 {1} else {0}
+}
+
+pub fn lint_script(script: &str) -> Vec<Problem> {
+    let mut problems : Vec<Problem> = Vec::new();
+    //Line of the last launcher that still needs a DELAY before anything gets typed.
+    let mut launcher : Option<usize> = None;
+    let mut seen_command : bool = false;
+    for (index, line) in script.lines().enumerate() {
+        let number : usize = index + 1;
+        let mut report = |severity: Severity, message: String| problems.push(Problem { line: number, severity, message });
+        let command : Command = match parse_line(line) {
+            Ok(Some(x)) => x,
+            Ok(None) => continue,
+            Err(message) => {report(Severity::Error, message); continue},
+        };
+        match &command {
+            Command::Rem(_) => continue,
+            Command::String(text) | Command::StringLn(text) | Command::AltString(text) | Command::AltCode(text) => {
+                if text.chars().count() > MAX_STRING_LENGTH {
+                    let name : &str = line.split(' ').next().unwrap_or("");
+                    report(Severity::Warning, format!("{} is {} characters long, split it to stay under {}", name, text.chars().count(), MAX_STRING_LENGTH));
+                }
+                if let Some(x) = launcher.take() {
+                    report(Severity::Warning, format!("typing right after the launcher on line {}, add a DELAY so the window can open", x));
+                }
+            },
+            Command::Delay(_) => launcher = None,
+            Command::Repeat(_) if !seen_command => report(Severity::Error, "REPEAT has no command before it to repeat".to_string()),
+            Command::Key(keys) if is_launcher(keys) => launcher = Some(number),
+            _ => (),
+        }
+        seen_command = true;
+    }
+    problems
+}
+
+//Key combinations that usually open a window, like GUI, GUI r or CTRL-ALT t.
+fn is_launcher(keys: &[String]) -> bool {
+    keys.first().is_some_and(|x| x.contains("GUI") || x == "WINDOWS" || x == "CTRL-ALT")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boilerplates::{POWERSHELL_BACKUP_FUNCTION, POWERSHELL_RESTORE_FUNCTION, POWERSHELL_RESTORE_POLICY, POWERSHELL_RMDIR_FUNCTION, POWERSHELL_SAVE_POLICY, UNIX_BACKUP_FUNCTION, UNIX_RESTORE_FUNCTION};
+    use crate::ducky::ScriptBuilder;
+    use crate::patch::POWERSHELL_PATCH_FUNCTION;
+
+    #[test]
+    fn the_helpers_busbi_types_pass_lint() {
+        let mut script : ScriptBuilder = ScriptBuilder::new();
+        for helper in [UNIX_BACKUP_FUNCTION, UNIX_RESTORE_FUNCTION, POWERSHELL_BACKUP_FUNCTION, POWERSHELL_RESTORE_FUNCTION, POWERSHELL_RMDIR_FUNCTION, POWERSHELL_PATCH_FUNCTION, &[POWERSHELL_SAVE_POLICY, POWERSHELL_RESTORE_POLICY]] {
+            script.string_lines(helper);
+        }
+        let problems : Vec<Problem> = lint_script(&script.to_string());
+        assert!(problems.is_empty(), "{}", problems.iter().map(|x| format!("line {}: {}", x.line, x.message)).collect::<Vec<String>>().join("\n"));
+    }
+}
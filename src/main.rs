@@ -1,50 +1,238 @@
-use std::{env, fs::{File, create_dir}};
+use std::{env, process, fs::{File, create_dir, write}};
 use std::io::{self, BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+mod archive;
+use archive::{pack_tar_gz, pack_zip};
 mod boilerplates;
-use boilerplates::{executable_boilerplate, start_boilerplate, end_boilerplate, make_file_boilerplate};
+mod crypto;
+mod ducky;
+use crypto::{encrypt_openssl, read_passphrase};
+mod encoding;
+mod explain;
+use explain::explain_command;
+mod extract;
+use extract::extract_command;
+use boilerplates::{compressed_boilerplate, removed_file_boilerplate, backup_boilerplate, backed_up_boilerplate, target_file_path, prompts_boilerplate, count_keystrokes, executable_boilerplate, start_boilerplate, end_boilerplate, make_file_boilerplate, mapped_file_boilerplate, execute_target_boilerplate};
 mod help_msg;
 use help_msg::{HelpMessage};
 mod arguments;
-use arguments::{parse_args_advanced};
+use arguments::{Arguments, BinaryMode, SourceEntry, parse_args_advanced, print_file_list};
 mod handlers;
-use handlers::{file_handler, d_flag_handler};
+mod hashes;
+use hashes::{FileHash, changes_since, hash_entries, hashes_path, read_hashes, write_hashes};
+use handlers::{file_handler, d_flag_handler, install_relative_path, is_text_file, resolve_target_path};
+mod ignore;
+mod inspect;
+use inspect::inspect_command;
+mod lint;
+use lint::lint_command;
+mod manifest;
+mod metadata;
+use metadata::Metadata;
+mod minify;
+mod patch;
+mod quoting;
+mod secrets;
+mod simulate;
+use simulate::simulate_command;
+mod template;
+mod uninstall;
+use uninstall::{uninstall_path, uninstall_script};
+use template::{check_templates, collect_prompts};
 
 fn main() -> io::Result<()> {
     let args : Vec<String> = env::args().collect();
 
-    let (target_os, execute, x_value, d_flag, m_flag, source_file, destination, list_files, close_window) = match parse_args_advanced(&args) {
+    //Commands that work on an existing bad USB script instead of creating one.
+    match args.get(1).map(|x| x.as_str()) {
+        Some("lint") => process::exit(lint_command(&args[2..])),
+        Some("simulate") => process::exit(simulate_command(&args[2..])),
+        Some("extract") => process::exit(extract_command(&args[2..])),
+        Some("explain") => process::exit(explain_command(&args[2..])),
+        Some("inspect") => process::exit(inspect_command(&args[2..])),
+        _ => (),
+    }
+
+    let arguments : Arguments = match parse_args_advanced(&args) {
         Ok(x) => x,
         Err(e) => match e {
             HelpMessage::PrintingHelp => {println!("\x1b[32mHope that helped :)\x1b[0m"); return Ok(())},
             _ => {println!("Got an error. Check your usage. Got: {:?}", e); println!("Use '--help' or '-h' for usage instructions."); return Ok(())},
         }
     };
+    let Arguments {target_os, execute, d_flag, m_flag, destination, close_window, keep_prefix, ..} = arguments;
+    let Arguments {ref x_value, source_path: ref source_file, ref mappings, ..} = arguments;
+
+    if arguments.list_only {
+        print_file_list(&arguments.list_files, source_file);
+        return Ok(())
+    }
+
+    //Every text file that is going to be typed, checked before anything gets created.
+    let text_files : Vec<&Path> = if d_flag || m_flag {
+        arguments.list_files.iter().filter_map(|x| match x {SourceEntry::File(path) => Some(path.as_path()), _ => None}).collect()
+    } else if !mappings.is_empty() {
+        mappings.iter().map(|x| x.source.as_path()).collect()
+    } else {
+        vec![source_file.as_path()]
+    };
+    //Everything the operator has to type on the target, asked for once at the start of the script.
+    let mut prompts : Vec<String> = Vec::new();
+    if let Some(vars) = &arguments.content.template {
+        if let Err(e) = check_templates(&text_files, vars) {
+            println!("Got an error: {:?}, nothing was created.", e);
+            return Ok(())
+        }
+        prompts = collect_prompts(&text_files);
+        if arguments.compress && !prompts.is_empty() {
+            println!("Error: prompts can't be filled in inside a '-z' archive, leave out '-z' to use them.");
+            return Ok(())
+        }
+    }
+    if arguments.encrypt && !d_flag {
+        println!("Error: '--encrypt' only works with '-d', it encrypts the packed directory.");
+        return Ok(())
+    }
+    if arguments.install_dir.is_some() && !d_flag {
+        println!("Error: '--install-dir' only works with '-d', the other modes don't install into a directory.");
+        return Ok(())
+    }
+    if arguments.since.is_some() && !d_flag {
+        println!("Error: '--since' only works with '-d', it updates a directory delivered before.");
+        return Ok(())
+    }
+    if let Some(baseline) = &arguments.patch {
+        if !d_flag || arguments.compress {
+            println!("Error: '--patch' only works with '-d' and without '-z', the archive always has whole files.");
+            return Ok(())
+        }
+        if !baseline.is_dir() {
+            println!("Error: the baseline '{}' given to '--patch' is not a directory.", baseline.display());
+            return Ok(())
+        }
+    }
+    if let Err(e) = arguments.secrets.check(&text_files, &arguments.source_path, &arguments.content) {
+        println!("Got an error: {:?}", e);
+        return Ok(())
+    }
+    //Every source that ends up on the target, text or not, for the header of the script.
+    let sources : Vec<&Path> = if d_flag || m_flag {
+        arguments.list_files.iter().filter_map(|x| match x {SourceEntry::File(path) | SourceEntry::Binary(path) => Some(path.as_path()), _ => None}).collect()
+    } else {
+        text_files.clone()
+    };
+    let mut metadata : Metadata = Metadata::new(target_os, &args, &sources);
+    //The names and hashes of what is in an encrypted archive would give its content away.
+    if arguments.encrypt {
+        metadata = metadata.without_sources();
+    }
 
     if m_flag {
         match create_dir(PathBuf::from(destination)) {
             Ok(_x) => println!("Made directory {}.", destination),
             Err(e) => {println!("Failed with following error: {}", e); return Ok(())},
         }
-        match file_handler(target_os, execute, list_files, destination, d_flag, m_flag, close_window) {
+        match file_handler(&arguments, &arguments.list_files, &metadata) {
             Ok(_x) => println!("\x1b[32mSuccesfully created files and directories.\x1b[0m"), 
             Err(e) => {println!("Got an error: {:?}, Check usage.", e); return Ok(())},
         }
     } else if d_flag {
+        let install_dest : &str = arguments.install_dest();
+        //Asked for once, it encrypts the archive, the hashes file and the uninstall script.
+        let passphrase : Option<String> = match arguments.encrypt {
+            true => match read_passphrase() {
+                Ok(x) => Some(x),
+                Err(e) => {println!("Got an error: {:?}", e); return Ok(())},
+            },
+            false => None,
+        };
+        let hashes : Vec<FileHash> = match hash_entries(&arguments.list_files, source_file, keep_prefix, &arguments.content) {
+            Ok(x) => x,
+            Err(e) => {println!("Failed to hash the files, got error: {}", e); return Ok(())},
+        };
+        //Everything gets delivered, unless only the changes since an earlier build are wanted.
+        let (entries, removed) : (Vec<SourceEntry>, Vec<String>) = match &arguments.since {
+            Some(since) => {
+                let earlier : Vec<FileHash> = match read_hashes(since, passphrase.as_deref()) {
+                    Ok(x) => x,
+                    Err(e) => {println!("Got an error: {:?}", e); return Ok(())},
+                };
+                let (entries, removed) = changes_since(&arguments.list_files, &hashes, &earlier, source_file, keep_prefix);
+                let changed : usize = entries.iter().filter(|x| matches!(x, SourceEntry::File(_) | SourceEntry::Binary(_))).count();
+                println!("Since {}: {} new or changed file(s), {} unchanged, {} removed.", since.display(), changed, hashes.len() - changed, removed.len());
+                (entries, removed)
+            },
+            None => (arguments.list_files.clone(), Vec::new()),
+        };
         let new_file = File::create(destination)?;
         //1MB capacity for the buffer, feel free to change this.  
         let mut write_buf = BufWriter::with_capacity(1000000, new_file);
-        let start_boiler: String = start_boilerplate(target_os, true, destination);
+        //An update goes into the directory that is already there.
+        let is_update : bool = arguments.since.is_some() || arguments.patch.is_some();
+        let start_boiler: String = start_boilerplate(target_os, !is_update, install_dest, &metadata);
         let _ = write_buf.write(start_boiler.as_bytes());
+        let _ = write_buf.write(prompts_boilerplate(target_os, &prompts).as_bytes());
+        if arguments.backup {
+            //Files that get patched have to stay where they are, those are copied.
+            let in_baseline = |path: &Path| -> bool {
+                match (&arguments.patch, path.strip_prefix(source_file)) {
+                    (Some(baseline), Ok(x)) => baseline.join(x).is_file(),
+                    _ => false,
+                }
+            };
+            let mut targets : Vec<(String, bool)> = entries.iter().filter_map(|x| match x {
+                SourceEntry::File(path) | SourceEntry::Binary(path) | SourceEntry::Symlink(path, _) => {
+                    let install_path : PathBuf = install_relative_path(path, source_file, keep_prefix);
+                    Some((target_file_path(target_os, path, &install_path, install_dest, true, false), in_baseline(path)))
+                },
+                SourceEntry::EmptyDir(_) => None,
+            }).collect();
+            targets.extend(removed.iter().map(|x| (target_file_path(target_os, Path::new(x), Path::new(x), install_dest, true, false), false)));
+            let _ = write_buf.write(backup_boilerplate(target_os, install_dest, &targets).as_bytes());
+        }
 
-        let grande_string : String = match d_flag_handler(target_os, execute, list_files, destination) {
+        let mut grande_string : String = match d_flag_handler(&arguments, entries.clone()) {
             Ok(x) => x,
             Err(e) => panic!("Got an error: {:?}", e),
         };
+        if arguments.compress {
+            let archive = if target_os == "windows" {
+                pack_zip(&entries, source_file, keep_prefix, &arguments.content)
+            } else {
+                pack_tar_gz(&entries, source_file, keep_prefix, &arguments.content)
+            };
+            let mut archive : Vec<u8> = match archive {
+                Ok(x) => x,
+                Err(e) => {println!("Failed to pack the directory, got error: {}", e); return Ok(())},
+            };
+            if let Some(passphrase) = &passphrase {
+                archive = match encrypt_openssl(&archive, passphrase) {
+                    Ok(x) => x,
+                    Err(e) => {println!("Got an error: {:?}", e); return Ok(())},
+                };
+            }
+            let compressed_string : String = compressed_boilerplate(target_os, &archive, install_dest, arguments.encrypt);
+            let plain_keys : usize = count_keystrokes(&grande_string);
+            let compressed_keys : usize = count_keystrokes(&compressed_string);
+            println!("Typing the files: {} keystrokes, compressed: {} keystrokes.", plain_keys, compressed_keys);
+            if compressed_keys < plain_keys {
+                println!("Compression saves {} keystrokes ({}%).", plain_keys - compressed_keys, (plain_keys - compressed_keys) * 100 / plain_keys);
+            } else {
+                println!("\x1b[33mCompression doesn't save anything for this directory, it still gets used.\x1b[0m");
+            }
+            grande_string = compressed_string;
+        }
+        for path in &removed {
+            grande_string.push_str(&removed_file_boilerplate(target_os, Path::new(path), install_dest));
+        }
         let _ = write_buf.write(grande_string.as_bytes());
+        if arguments.backup {
+            let _ = write_buf.write(backed_up_boilerplate(target_os).as_bytes());
+        }
         if execute {
-            let execute_boiler : String = match executable_boilerplate(target_os, &x_value, destination, d_flag, m_flag) {
+            let x_install_path : PathBuf = install_relative_path(x_value, source_file, keep_prefix);
+            let execute_boiler : String = match executable_boilerplate(target_os, &x_install_path, install_dest, d_flag, m_flag) {
                 Ok(x) => x,
                 Err(e) => panic!("Got an error: {:?}",e),
             };
@@ -58,16 +246,94 @@ fn main() -> io::Result<()> {
             Ok(_x) => println!("\x1b[32mSuccessfully wrote file.\x1b[0m"),
             Err(e) => {println!("Got error: {}", e); return Ok(())},  
         };
+        //Every file, not only the ones typed by this script, so the next '--since' can start from here.
+        let hashes_file : PathBuf = hashes_path(destination);
+        match write_hashes(&hashes_file, &hashes, passphrase.as_deref()) {
+            Ok(_x) if passphrase.is_some() => println!("Wrote the file hashes to {}, encrypted with the passphrase.", hashes_file.display()),
+            Ok(_x) => println!("Wrote the file hashes to {}.", hashes_file.display()),
+            Err(e) => println!("Could not write {}, got error: {}", hashes_file.display(), e),
+        };
+        let uninstall_file : PathBuf = uninstall_path(destination);
+        let uninstall : String = match uninstall_script(&arguments, &metadata, passphrase.as_deref()) {
+            Ok(x) => x,
+            Err(e) => {println!("Could not make the uninstall script, got an error: {:?}", e); return Ok(())},
+        };
+        match write(&uninstall_file, uninstall) {
+            Ok(_x) if passphrase.is_some() => println!("Wrote the uninstall script to {}, it asks for the passphrase.", uninstall_file.display()),
+            Ok(_x) => println!("Wrote the uninstall script to {}.", uninstall_file.display()),
+            Err(e) => println!("Could not write {}, got error: {}", uninstall_file.display(), e),
+        };
+
+    } else if !mappings.is_empty() {
+        for mapping in mappings {
+            match is_text_file(&mapping.source) {
+                Ok(true) => (),
+                //Binary files get typed as base64 by mapped_file_boilerplate().
+                Ok(false) if arguments.binary == BinaryMode::Encode => (),
+                Ok(false) => {println!("Error: {} is not a text file, use '--binary encode' to deliver it base64 encoded.", mapping.source.display()); return Ok(())},
+                Err(e) => {println!("Err: File you specified doesn't exist or something else went wrong. Your file: {}, got: {}", mapping.source.display(), e); return Ok(())},
+            };
+        }
+        let new_file = File::create(destination)?;
+        let mut write_buf = BufWriter::with_capacity(1000000, new_file);
+        let start_boiler: String = start_boilerplate(target_os, false, destination, &metadata);
+        let _ = write_buf.write(start_boiler.as_bytes());
+        let _ = write_buf.write(prompts_boilerplate(target_os, &prompts).as_bytes());
+        if arguments.backup {
+            let targets : Vec<(String, bool)> = mappings.iter().map(|x| (resolve_target_path(target_os, &x.target), false)).collect();
+            let _ = write_buf.write(backup_boilerplate(target_os, destination, &targets).as_bytes());
+        }
+        for mapping in mappings {
+            let file_content: String = mapped_file_boilerplate(target_os, &mapping.source, &mapping.target, mapping.mode, &arguments.content);
+            let _ = write_buf.write(file_content.as_bytes());
+        }
+        if arguments.backup {
+            let _ = write_buf.write(backed_up_boilerplate(target_os).as_bytes());
+        }
+        if execute {
+            //The file to execute is named by its source path, it runs from wherever it was mapped to.
+            let target : &str = match mappings.iter().find(|m| m.source == *x_value) {
+                Some(x) => &x.target,
+                None => {println!("Error: '{}' given to '-x' is not one of the mapped files.", x_value.display()); return Ok(())},
+            };
+            let execute_boiler : String = match execute_target_boilerplate(target_os, target) {
+                Ok(x) => x,
+                Err(e) => panic!("Got an error: {:?}", e),
+            };
+            let _ = write_buf.write(execute_boiler.as_bytes());
+        } else {
+            let end_boiler: String = end_boilerplate(target_os, close_window);
+            let _ = write_buf.write(end_boiler.as_bytes());
+        }
+        match write_buf.flush() {
+            Ok(_x) => println!("\x1b[32mSuccessfully wrote file.\x1b[0m"),
+            Err(e) => {println!("Got error: {}", e); return Ok(())},
+        };
 
     } else {
         let new_file = File::create(destination)?;
         let mut write_buf = BufWriter::with_capacity(1000000, new_file);
-        let start_boiler: String = start_boilerplate(target_os, false, destination);
+        let start_boiler: String = start_boilerplate(target_os, false, destination, &metadata);
         let _ = write_buf.write(start_boiler.as_bytes());
-        let file_content: String = make_file_boilerplate(target_os, &source_file, destination, false, false);
+        let _ = write_buf.write(prompts_boilerplate(target_os, &prompts).as_bytes());
+        match is_text_file(source_file) {
+            Ok(true) => (),
+            //Binary files get typed as base64 by make_file_boilerplate().
+            Ok(false) if arguments.binary == BinaryMode::Encode => (),
+            Ok(false) => {println!("Error: {} is not a text file, use '--binary encode' to deliver it base64 encoded.", source_file.display()); return Ok(())},
+            Err(e) => {println!("Err: File you specified doesn't exist or something else went wrong. Got: {}", e); return Ok(())},
+        };
+        if arguments.backup {
+            let target : String = target_file_path(target_os, source_file, source_file, destination, false, false);
+            let _ = write_buf.write(backup_boilerplate(target_os, destination, &[(target, false)]).as_bytes());
+        }
+        let file_content: String = make_file_boilerplate(target_os, source_file, source_file, destination, false, false, &arguments.content);
         let _ = write_buf.write(file_content.as_bytes());
+        if arguments.backup {
+            let _ = write_buf.write(backed_up_boilerplate(target_os).as_bytes());
+        }
         if execute {
-            let execute_boiler : String = match executable_boilerplate(target_os, &x_value, destination, d_flag, m_flag) {
+            let execute_boiler : String = match executable_boilerplate(target_os, x_value, destination, d_flag, m_flag) {
                 Ok(x) => x, 
                 Err(e) => panic!("Couldn't work with given x flag value, got error: {:?}", e),
             };
@@ -0,0 +1,127 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::handlers::{changed_file_mode, file_mode};
+use crate::help_msg::HelpMessage;
+use crate::ignore::{IgnoreRule, relative_rule_path};
+
+//A single source file on this machine and the path it should end up at on the target.
+#[derive(Debug)]
+pub struct FileMapping {
+    pub source: PathBuf,
+    pub target: String,
+    //Unix permissions to set on the target instead of the ones the source file has.
+    pub mode: Option<u32>,
+}
+
+//Parses the value given to '--map', which looks like 'dotfiles/bashrc=~/.bashrc'. Options for the
+//file can follow after a ';', like 'ssh/config=~/.ssh/config;mode=0600'.
+pub fn parse_mapping(value: &str) -> Result<FileMapping, HelpMessage> {
+    let (paths, options) : (&str, &str) = value.split_once(';').unwrap_or((value, ""));
+    let (source, target) = match paths.split_once('=') {
+        Some(x) => x,
+        None => {println!("Error: '{}' is not a valid mapping, use 'source=target'.", value); return Err(HelpMessage::InvalidMapping)},
+    };
+    let source : &str = source.trim();
+    let target : &str = target.trim();
+    if source.is_empty() || target.is_empty() {
+        println!("Error: '{}' is missing a source or a target.", value);
+        return Err(HelpMessage::InvalidMapping)
+    }
+    let mut mapping = FileMapping { source: PathBuf::from(source), target: target.to_string(), mode: None };
+    for option in options.split([';', ',', ' ']).filter(|x| !x.is_empty()) {
+        match option.split_once('=') {
+            Some(("mode", x)) => mapping.mode = Some(parse_mode(x)?),
+            _ => {println!("Error: unknown option '{}' in '{}'.", option, value); return Err(HelpMessage::InvalidMapping)},
+        }
+    }
+    Ok(mapping)
+}
+
+//Reads an octal permission like '0600' or '755'.
+fn parse_mode(value: &str) -> Result<u32, HelpMessage> {
+    match u32::from_str_radix(value, 8) {
+        Ok(x) if x <= 0o7777 => Ok(x),
+        _ => {println!("Error: '{}' is not a valid octal file mode.", value); Err(HelpMessage::InvalidMapping)},
+    }
+}
+
+//A '--mode' override for files delivered with '-d' or '-m', like 'bin/*.sh=0755'. The pattern
+//works like a line of a .gitignore file, relative to the source directory.
+#[derive(Debug, Clone)]
+pub struct ModeOverride {
+    rule: IgnoreRule,
+    mode: u32,
+}
+
+//The '--mode' overrides and the source directory their patterns are relative to.
+#[derive(Debug, Clone, Default)]
+pub struct ModeOverrides {
+    pub root: PathBuf,
+    pub overrides: Vec<ModeOverride>,
+}
+
+pub fn parse_mode_override(value: &str) -> Result<ModeOverride, HelpMessage> {
+    let (pattern, mode) = match value.rsplit_once('=') {
+        Some(x) => x,
+        None => {println!("Error: '{}' is not a valid mode override, use 'pattern=mode'.", value); return Err(HelpMessage::InvalidModeOverride)},
+    };
+    let rule : IgnoreRule = match IgnoreRule::parse(pattern.trim()) {
+        Some(x) if !x.is_negated() => x,
+        _ => {println!("Error: '{}' is not a valid pattern for '--mode'.", pattern); return Err(HelpMessage::InvalidModeOverride)},
+    };
+    let mode : u32 = parse_mode(mode.trim()).map_err(|_| HelpMessage::InvalidModeOverride)?;
+    Ok(ModeOverride { rule, mode })
+}
+
+impl ModeOverrides {
+    //The mode of the last override that matches the file.
+    fn override_mode(&self, path: &Path) -> Option<u32> {
+        //A single source file is its own root, it is matched by its name.
+        let relative : String = match path.strip_prefix(&self.root) {
+            Ok(x) if x.as_os_str().is_empty() => path.file_name().map(|y| y.to_string_lossy().into_owned()).unwrap_or_default(),
+            _ => relative_rule_path(path, &self.root),
+        };
+        self.overrides.iter().rev().find(|x| x.rule.matches(&relative, false)).map(|x| x.mode)
+    }
+
+    //The permissions a delivered file gets on the target, from the last override that matches it
+    //or else from the source file.
+    pub fn mode(&self, path: &Path) -> Option<u32> {
+        self.override_mode(path).or(file_mode(path))
+    }
+
+    //Like mode(), but None when there is no override and the source file has the permissions a new
+    //file gets on the target anyway, so there is nothing to chmod.
+    pub fn chmod_mode(&self, path: &Path) -> Option<u32> {
+        self.override_mode(path).or(changed_file_mode(path))
+    }
+}
+
+//Reads a manifest file with one 'source = target' mapping per line, optionally followed by
+//'; mode=0600' to override the permissions. Empty lines and lines starting with '#' are ignored.
+//Relative sources are taken relative to the manifest itself so the manifest can live next to the
+//dotfiles it describes.
+pub fn read_manifest(manifest_path: &Path) -> Result<Vec<FileMapping>, HelpMessage> {
+    let contents : String = match read_to_string(manifest_path) {
+        Ok(x) => x,
+        Err(e) => {println!("Could not read manifest '{}', got error: {}", manifest_path.display(), e); return Err(HelpMessage::FailedReadingManifest)},
+    };
+    let base_dir : &Path = manifest_path.parent().unwrap_or(Path::new(""));
+    let mut mappings : Vec<FileMapping> = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line : &str = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut mapping = match parse_mapping(line) {
+            Ok(x) => x,
+            Err(e) => {println!("Manifest error on line {}.", number + 1); return Err(e)},
+        };
+        if mapping.source.is_relative() {
+            mapping.source = base_dir.join(&mapping.source);
+        }
+        mappings.push(mapping);
+    }
+    Ok(mappings)
+}
@@ -0,0 +1,109 @@
+use std::env;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::archive::civil_from_unix;
+use crate::ducky::{Command, ScriptBuilder, parse_line};
+use crate::handlers::hash_file;
+use crate::quoting::quote_unix_word;
+
+// Provenance for generated scripts. start_boilerplate() puts a header of REM lines at the top of
+// every script with the busbi version, when it was built, the target OS, the options used and the
+// SHA-256 of every source file, or only how many there are for '--encrypt'. 'busbi inspect' reads
+// it back, to tell which version of a config a payload on a Flipper contains.
+
+const PREFIX : &str = "busbi-";
+//Makes builds reproducible, like other build tools do.
+const SOURCE_DATE_VAR : &str = "SOURCE_DATE_EPOCH";
+
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub version: String,
+    //UTC, like 2024-05-01T12:00:00Z.
+    pub built: String,
+    pub target_os: String,
+    pub options: String,
+    //The source path as given and the SHA-256 of its content.
+    pub sources: Vec<(String, String)>,
+    //The number of sources when they are left out of the header.
+    pub hidden_sources: usize,
+}
+
+impl Metadata {
+    //'args' is the whole command line. Values of '--var' are left out, they can be secrets.
+    pub fn new(target_os: &str, args: &[String], sources: &[&Path]) -> Metadata {
+        let mut options : Vec<String> = Vec::new();
+        for (index, arg) in args.iter().enumerate().skip(1) {
+            match arg.split_once('=') {
+                Some((name, _value)) if args[index - 1] == "--var" => options.push(format!("{}=...", name)),
+                _ => options.push(quote_unix_word(arg)),
+            }
+        }
+        let seconds : u64 = env::var(SOURCE_DATE_VAR).ok().and_then(|x| x.parse().ok())
+            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0));
+        let (year, month, day, hour, minute, second) = civil_from_unix(seconds);
+        let metadata = Metadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            built: format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second),
+            target_os: target_os.to_lowercase(),
+            options: options.join(" "),
+            sources: Vec::new(),
+            hidden_sources: 0,
+        };
+        metadata.with_sources(sources)
+    }
+
+    //The same metadata for a script that only delivers some of the sources, like with '-m'.
+    pub fn with_sources(&self, sources: &[&Path]) -> Metadata {
+        let hashed : Vec<(String, String)> = sources.iter()
+            .map(|x| (x.display().to_string(), hash_file(x).unwrap_or_else(|_e| "unreadable".to_string())))
+            .collect();
+        Metadata { sources: hashed, ..self.clone() }
+    }
+
+    //Only keeps the number of sources.
+    pub fn without_sources(&self) -> Metadata {
+        Metadata { sources: Vec::new(), hidden_sources: self.sources.len(), ..self.clone() }
+    }
+
+    pub fn add_to(&self, script: &mut ScriptBuilder) {
+        script.rem(&format!("{}version: {}", PREFIX, self.version))
+            .rem(&format!("{}built: {}", PREFIX, self.built))
+            .rem(&format!("{}target: {}", PREFIX, self.target_os))
+            .rem(&format!("{}options: {}", PREFIX, self.options));
+        for (path, hash) in &self.sources {
+            script.rem(&format!("{}source: {}  {}", PREFIX, hash, path));
+        }
+        if self.hidden_sources > 0 {
+            script.rem(&format!("{}sources: {}", PREFIX, self.hidden_sources));
+        }
+    }
+
+    //Reads the header from the REM lines at the top of a script, None if there is none.
+    pub fn parse(script: &str) -> Option<Metadata> {
+        let mut metadata : Metadata = Metadata::default();
+        for line in script.lines() {
+            let text : String = match parse_line(line) {
+                Ok(Some(Command::Rem(x))) => x,
+                Ok(None) => continue,
+                _ => break,
+            };
+            let Some((key, value)) = text.strip_prefix(PREFIX).and_then(|x| x.split_once(": ")) else {continue};
+            match key {
+                "version" => metadata.version = value.to_string(),
+                "built" => metadata.built = value.to_string(),
+                "target" => metadata.target_os = value.to_string(),
+                "options" => metadata.options = value.to_string(),
+                "sources" => metadata.hidden_sources = value.parse().unwrap_or(0),
+                "source" => if let Some((hash, path)) = value.split_once("  ") {
+                    metadata.sources.push((path.to_string(), hash.to_string()));
+                },
+                _ => (),
+            }
+        }
+        if metadata.version.is_empty() {
+            return None
+        }
+        Some(metadata)
+    }
+}
@@ -0,0 +1,214 @@
+use std::path::Path;
+
+// Strips full-line comments and blank lines from scripts and config files before they are typed,
+// since every character costs typing time. Only languages busbi knows get touched, and only lines
+// that are nothing but a comment, so code with a '#' further along the line stays as it is.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Language {
+    Shell,
+    PowerShell,
+    Python,
+    //INI and TOML, which also covers most '.conf' and '.cfg' files.
+    Config,
+}
+
+//Works out the language from the file extension, or from the shebang for scripts without one.
+fn detect_language(source_file: &Path, first_line: Option<&String>) -> Option<Language> {
+    let extension : String = source_file.extension().map(|x| x.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match extension.as_str() {
+        "sh" | "bash" | "zsh" | "ksh" => return Some(Language::Shell),
+        "ps1" | "psm1" | "psd1" => return Some(Language::PowerShell),
+        "py" | "pyw" => return Some(Language::Python),
+        "ini" | "toml" | "cfg" | "conf" => return Some(Language::Config),
+        _ => (),
+    }
+    let shebang : &str = first_line?.strip_prefix("#!")?;
+    if shebang.contains("python") {
+        Some(Language::Python)
+    } else if shebang.contains("pwsh") || shebang.contains("powershell") {
+        Some(Language::PowerShell)
+    } else if ["sh", "bash", "zsh", "ksh", "dash"].iter().any(|x| shebang.ends_with(x) || shebang.contains(&format!("{} ", x))) {
+        Some(Language::Shell)
+    } else {
+        None
+    }
+}
+
+//Lines that look like comments but mean something to the interpreter.
+fn must_keep(line: &str, index: usize, language: Language) -> bool {
+    let lower : String = line.trim().to_lowercase();
+    (index == 0 && lower.starts_with("#!"))
+        || (language == Language::PowerShell && lower.starts_with("#requires"))
+        || lower.starts_with("# requires")
+        || (language == Language::Python && index < 2 && lower.starts_with('#') && lower.contains("coding"))
+}
+
+fn is_comment(line: &str, language: Language) -> bool {
+    let trimmed : &str = line.trim();
+    match language {
+        Language::Config => trimmed.starts_with('#') || trimmed.starts_with(';'),
+        //A '<# #>' block comment that fits on its line.
+        Language::PowerShell => trimmed.starts_with('#') || (trimmed.starts_with("<#") && trimmed.ends_with("#>") && trimmed.matches("#>").count() == 1),
+        _ => trimmed.starts_with('#'),
+    }
+}
+
+//What a PowerShell line opens that runs over the lines after it.
+enum PowerShellBlock {
+    //A '<#' comment without its '#>' yet, with its lines so far and whether it started its line.
+    Comment(Vec<String>, bool),
+    //A @' '@ or @" "@ here-string, with its quote character.
+    HereString(char),
+}
+
+//If the line opens a PowerShell here-string or block comment that ends on a later line, gives it.
+fn powershell_block(line: &str) -> Option<PowerShellBlock> {
+    let trimmed : &str = line.trim_end();
+    for quote in ['\'', '"'] {
+        if trimmed.ends_with(&format!("@{}", quote)) {
+            return Some(PowerShellBlock::HereString(quote))
+        }
+    }
+    let start : usize = line.rfind("<#")?;
+    if line[start..].contains("#>") {
+        return None
+    }
+    //Only a comment that has its lines to itself can be left out.
+    Some(PowerShellBlock::Comment(Vec::new(), line.trim_start().starts_with("<#") && line.find("<#") == Some(start)))
+}
+
+//If the line starts a shell here-document, gives the word that ends it.
+fn heredoc_delimiter(line: &str) -> Option<String> {
+    let start : usize = line.find("<<")?;
+    let rest : &str = &line[start + 2..];
+    //'<<<' is a here-string, not a here-document.
+    if rest.starts_with('<') {
+        return None
+    }
+    let rest : &str = rest.trim_start_matches('-').trim_start();
+    let word : String = rest.chars()
+        .skip_while(|c| *c == '\'' || *c == '"')
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    if word.is_empty() {None} else {Some(word)}
+}
+
+//Gives the lines with comments and blank lines left out, or None when the language of the file is
+//unknown and it has to be typed as it is. Here-documents in shell scripts, triple quoted strings
+//in Python and here-strings in PowerShell are content and are never touched. PowerShell block
+//comments are left out when they have their lines to themselves and kept as they are otherwise.
+pub fn minify_lines(source_file: &Path, lines: &[String]) -> Option<Vec<String>> {
+    let language : Language = detect_language(source_file, lines.first())?;
+    let mut kept : Vec<String> = Vec::new();
+    let mut heredoc_end : Option<String> = None;
+    let mut in_python_string : bool = false;
+    let mut powershell : Option<PowerShellBlock> = None;
+    for (index, line) in lines.iter().enumerate() {
+        match &mut powershell {
+            Some(PowerShellBlock::HereString(quote)) => {
+                //The closing '@ or "@ has to be at the very start of its line.
+                if line.starts_with(*quote) && line[1..].starts_with('@') {
+                    powershell = None;
+                }
+                kept.push(line.clone());
+                continue;
+            },
+            Some(PowerShellBlock::Comment(comment, own_lines)) => {
+                comment.push(line.clone());
+                if let Some(end) = line.find("#>") {
+                    let rest : &str = &line[end + 2..];
+                    if !*own_lines || !rest.trim().is_empty() {
+                        kept.append(comment);
+                    }
+                    powershell = powershell_block(rest).map(|x| match x {
+                        PowerShellBlock::Comment(y, _) => PowerShellBlock::Comment(y, false),
+                        y => y,
+                    });
+                }
+                continue;
+            },
+            None => (),
+        }
+        if let Some(end) = &heredoc_end {
+            if line.trim_start_matches('\t') == end {
+                heredoc_end = None;
+            }
+            kept.push(line.clone());
+            continue;
+        }
+        if in_python_string {
+            if (line.matches("\"\"\"").count() + line.matches("'''").count()) % 2 == 1 {
+                in_python_string = false;
+            }
+            kept.push(line.clone());
+            continue;
+        }
+        if must_keep(line, index, language) {
+            kept.push(line.clone());
+            continue;
+        }
+        if line.trim().is_empty() || is_comment(line, language) {
+            continue;
+        }
+        match language {
+            Language::Shell => heredoc_end = heredoc_delimiter(line),
+            Language::Python => in_python_string = (line.matches("\"\"\"").count() + line.matches("'''").count()) % 2 == 1,
+            Language::PowerShell => powershell = powershell_block(line),
+            _ => (),
+        }
+        match &mut powershell {
+            Some(PowerShellBlock::Comment(comment, _)) => comment.push(line.clone()),
+            _ => kept.push(line.clone()),
+        }
+    }
+    //A comment that is never closed is kept, PowerShell will say what is wrong with it.
+    if let Some(PowerShellBlock::Comment(comment, _)) = &mut powershell {
+        kept.append(comment);
+    }
+    Some(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minify(name: &str, text: &str) -> Vec<String> {
+        let lines : Vec<String> = text.lines().map(|x| x.to_string()).collect();
+        minify_lines(Path::new(name), &lines).unwrap()
+    }
+
+    #[test]
+    fn powershell_block_comments_are_left_out_whole() {
+        let text : &str = "<#\n.SYNOPSIS\n  Does things.\n\n#>\nWrite-Host 'a'\n<# one line #>\n# line comment\nWrite-Host 'b'";
+        assert_eq!(minify("a.ps1", text), ["Write-Host 'a'", "Write-Host 'b'"]);
+    }
+
+    #[test]
+    fn powershell_block_comments_next_to_code_are_kept() {
+        let text : &str = "$a = 1 <# starts here\n# still the comment\n\nends here #>\n<# before\n#> $b = 2\n# gone";
+        assert_eq!(minify("a.ps1", text), ["$a = 1 <# starts here", "# still the comment", "", "ends here #>", "<# before", "#> $b = 2"]);
+    }
+
+    #[test]
+    fn powershell_here_strings_are_kept_as_they_are() {
+        let text : &str = "$a = @\"\n# not a comment\n\n  <# nor this\n\"@\n# comment\n$b = @'\n\n#x\n '@ still in\n'@\n\nWrite-Host $a";
+        assert_eq!(minify("a.ps1", text), ["$a = @\"", "# not a comment", "", "  <# nor this", "\"@", "$b = @'", "", "#x", " '@ still in", "'@", "Write-Host $a"]);
+    }
+
+    #[test]
+    fn powershell_requires_and_unclosed_comments_are_kept() {
+        assert_eq!(minify("a.ps1", "#Requires -Version 5\n<#\nnever closed"), ["#Requires -Version 5", "<#", "never closed"]);
+    }
+
+    #[test]
+    fn shell_heredocs_and_python_strings_are_kept() {
+        assert_eq!(minify("a.sh", "#!/bin/sh\n# c\ncat <<EOF\n# kept\n\nEOF\n# gone"), ["#!/bin/sh", "cat <<EOF", "# kept", "", "EOF"]);
+        assert_eq!(minify("a.py", "x = \"\"\"\n# kept\n\n\"\"\"\n# gone"), ["x = \"\"\"", "# kept", "", "\"\"\""]);
+    }
+
+    #[test]
+    fn unknown_files_are_not_touched() {
+        assert!(minify_lines(Path::new("a.txt"), &["# x".to_string()]).is_none());
+    }
+}
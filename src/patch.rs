@@ -0,0 +1,292 @@
+// Patch mode, '--patch <baseline>'. The baseline is a copy of the source directory as it was
+// delivered before. Files that changed since are typed as a unified diff against their baseline
+// version and patched on the target, with 'patch' on Unix and the busbi_patch function below on
+// Windows, whenever the diff is fewer keystrokes than the whole file. Files that didn't change
+// aren't typed at all.
+//
+// Both sides are compared as they get typed, with placeholders filled in and whitespace only lines
+// empty, see delivered_lines(). Busbi applies every diff to the baseline itself before using it and
+// types the whole file instead when that doesn't give the new version. A file on the target that
+// isn't the baseline version is left alone, with an error in the terminal. 'patch' on Unix also
+// places a hunk when the lines around it moved, so changes made on the target elsewhere in the
+// file are kept.
+
+//Lines of context around every change, like 'diff -u'.
+const CONTEXT : usize = 3;
+//Diffs with more changed lines than this are not worth it, the file gets typed in full.
+const MAX_EDITS : usize = 2000;
+
+//Applies a diff on Windows targets, typed once before the first patch. Works like apply_patch(),
+//and leaves the file alone when it isn't the version the diff was made for.
+pub const POWERSHELL_PATCH_FUNCTION : &[&str] = &[
+    "function busbi_patch($path, $diff) {",
+    "if (-not (Test-Path -LiteralPath $path)) { Write-Error \"busbi: could not patch $path, it does not exist\"; return }",
+    "$old = @(Get-Content -LiteralPath $path); $new = [Collections.Generic.List[string]]::new(); $i = 0; $hunk = $false",
+    "foreach ($l in $diff -split \"`r?`n\") {",
+    "if ($l -match '^@@ -(\\d+)(,(\\d+))?') { $hunk = $true; $s = [int]$Matches[1]; if ($Matches[3] -ne '0') { $s-- }",
+    "while ($i -lt $s) { $new.Add($old[$i]); $i++ }; continue }",
+    "if (-not $hunk) { continue }; if ($l.StartsWith('+')) { $new.Add($l.Substring(1)); continue }",
+    "$t = if ($l.Length) { $l.Substring(1) } else { '' }",
+    "if ($i -ge $old.Count -or $old[$i] -cne $t) { Write-Error \"busbi: could not patch $path, it is not the version the patch was made for\"; return }",
+    "if (-not $l.StartsWith('-')) { $new.Add($t) }; $i++ }",
+    "while ($i -lt $old.Count) { $new.Add($old[$i]); $i++ }; Set-Content -LiteralPath $path -Value $new }",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+//The shortest edit script from 'old' to 'new' with Myers' algorithm, None if it needs more than
+//MAX_EDITS changes.
+fn edits(old: &[String], new: &[String]) -> Option<Vec<Edit>> {
+    let (n, m) : (isize, isize) = (old.len() as isize, new.len() as isize);
+    let limit : isize = (n + m).min(MAX_EDITS as isize);
+    let offset : isize = limit + 1;
+    let mut v : Vec<isize> = vec![0; 2 * offset as usize + 1];
+    //The furthest x on every diagonal k, kept for every d to walk back from the end.
+    let mut trace : Vec<Vec<isize>> = Vec::new();
+    let mut found : bool = false;
+    'search: for d in 0..=limit {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x : isize = if k == -d || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize]) {
+                v[(offset + k + 1) as usize]
+            } else {
+                v[(offset + k - 1) as usize] + 1
+            };
+            let mut y : isize = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'search
+            }
+        }
+    }
+    if !found {
+        return None
+    }
+    let mut script : Vec<Edit> = Vec::new();
+    let (mut x, mut y) : (isize, isize) = (n, m);
+    for (d, row) in trace.iter().enumerate().rev() {
+        let d : isize = d as isize;
+        let k : isize = x - y;
+        let (prev_x, prev_y) : (isize, isize) = if d == 0 {
+            (0, 0)
+        } else {
+            //'row' holds the diagonals -d..=d of the step before.
+            let at = |k: isize| row[(k + d) as usize];
+            let prev_k : isize = if k == -d || (k != d && at(k - 1) < at(k + 1)) {k + 1} else {k - 1};
+            (at(prev_k), at(prev_k) - prev_k)
+        };
+        while x > prev_x && y > prev_y {
+            script.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            script.push(if x == prev_x {Edit::Insert} else {Edit::Delete});
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    script.reverse();
+    Some(script)
+}
+
+//A unified diff from 'old' to 'new' for the file at 'path', empty if they are the same and None if
+//there are too many changes.
+pub fn unified_diff(old: &[String], new: &[String], path: &str) -> Option<Vec<String>> {
+    let script : Vec<Edit> = edits(old, new)?;
+    //The line of 'old' and of 'new' every edit starts at.
+    let mut positions : Vec<(usize, usize)> = Vec::with_capacity(script.len() + 1);
+    let (mut i, mut j) : (usize, usize) = (0, 0);
+    for edit in &script {
+        positions.push((i, j));
+        match edit {
+            Edit::Keep => {i += 1; j += 1},
+            Edit::Delete => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+    positions.push((i, j));
+    let changes : Vec<usize> = script.iter().enumerate().filter(|x| *x.1 != Edit::Keep).map(|x| x.0).collect();
+    let mut diff : Vec<String> = Vec::new();
+    if changes.is_empty() {
+        return Some(diff)
+    }
+    diff.push(format!("--- a/{}", path));
+    diff.push(format!("+++ b/{}", path));
+    let mut index : usize = 0;
+    while index < changes.len() {
+        //Changes closer together than twice the context go in the same hunk.
+        let mut last : usize = index;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= 2 * CONTEXT {
+            last += 1;
+        }
+        let start : usize = changes[index].saturating_sub(CONTEXT);
+        let end : usize = (changes[last] + CONTEXT + 1).min(script.len());
+        let old_count : usize = script[start..end].iter().filter(|x| **x != Edit::Insert).count();
+        let new_count : usize = script[start..end].iter().filter(|x| **x != Edit::Delete).count();
+        //An empty range is given by the line before it, like diff does.
+        let (old_start, new_start) : (usize, usize) = positions[start];
+        diff.push(format!("@@ -{},{} +{},{} @@",
+            if old_count == 0 {old_start} else {old_start + 1}, old_count,
+            if new_count == 0 {new_start} else {new_start + 1}, new_count));
+        for (edit, (i, j)) in script[start..end].iter().zip(&positions[start..end]) {
+            match edit {
+                Edit::Keep => diff.push(format!(" {}", old[*i])),
+                Edit::Delete => diff.push(format!("-{}", old[*i])),
+                Edit::Insert => diff.push(format!("+{}", new[*j])),
+            }
+        }
+        index = last + 1;
+    }
+    Some(diff)
+}
+
+//Applies a diff from unified_diff() to 'old' the way busbi_patch does on the target, None if it
+//doesn't fit. An empty line in a hunk is an empty context line, that is how it arrives typed.
+pub fn apply_patch(old: &[String], diff: &[String]) -> Option<Vec<String>> {
+    let mut new : Vec<String> = Vec::new();
+    let mut index : usize = 0;
+    let mut in_hunk : bool = false;
+    for line in diff {
+        if let Some(range) = line.strip_prefix("@@ -").and_then(|x| x.split(' ').next()) {
+            in_hunk = true;
+            let (start, count) : (usize, usize) = match range.split_once(',') {
+                Some((start, count)) => (start.parse().ok()?, count.parse().ok()?),
+                None => (range.parse().ok()?, 1),
+            };
+            let start : usize = if count == 0 {start} else {start.saturating_sub(1)};
+            if start < index || start > old.len() {
+                return None
+            }
+            new.extend_from_slice(&old[index..start]);
+            index = start;
+        } else if !in_hunk {
+            continue
+        } else if let Some(added) = line.strip_prefix('+') {
+            new.push(added.to_string());
+        } else {
+            let text : &str = line.get(1..).unwrap_or("");
+            if old.get(index).map(|x| x.as_str()) != Some(text) {
+                return None
+            }
+            if !line.starts_with('-') {
+                new.push(text.to_string());
+            }
+            index += 1;
+        }
+    }
+    new.extend_from_slice(&old[index..]);
+    Some(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boilerplates::{ContentOptions, delivered_lines};
+    use std::env;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::path::PathBuf;
+    use std::process::{self, Command};
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|x| x.to_string()).collect()
+    }
+
+    //Diffs, types the diff like baseline_patch() does and applies it again.
+    fn round_trip(old: &[String], new: &[String]) -> Vec<String> {
+        let diff : Vec<String> = unified_diff(old, new, "f.txt").unwrap();
+        let typed : Vec<String> = diff.iter().map(|x| if x.trim().is_empty() {String::new()} else {x.clone()}).collect();
+        let patched : Vec<String> = apply_patch(old, &typed).unwrap();
+        assert_eq!(patched, new, "diff was:\n{}", diff.join("\n"));
+        diff
+    }
+
+    #[test]
+    fn round_trips_edits_anywhere_in_the_file() {
+        let old : Vec<String> = lines("a\nb\nc\nd\ne\nf\ng\nh\ni\nj");
+        let cases : [&str; 7] = [
+            "start\na\nb\nc\nd\ne\nf\ng\nh\ni\nj",
+            "b\nc\nd\ne\nf\ng\nh\ni\nj",
+            "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nend",
+            "a\nb\nc\nd\ne\nf\ng\nh\ni",
+            "A\nb\nc\nd\ne\nf\ng\nh\ni\nJ",
+            "a\nb\n\nc\nd\nE\nf\ng\nh\n\ni\nj",
+            "x\ny",
+        ];
+        for new in cases {
+            round_trip(&old, &lines(new));
+        }
+    }
+
+    #[test]
+    fn round_trips_from_and_to_an_empty_file() {
+        let diff : Vec<String> = round_trip(&[], &lines("a\nb"));
+        assert_eq!(diff[2], "@@ -0,0 +1,2 @@");
+        let diff : Vec<String> = round_trip(&lines("a\nb"), &[]);
+        assert_eq!(diff[2], "@@ -1,2 +0,0 @@");
+    }
+
+    #[test]
+    fn identical_files_give_an_empty_diff() {
+        assert!(unified_diff(&lines("a\nb"), &lines("a\nb"), "f.txt").unwrap().is_empty());
+    }
+
+    #[test]
+    fn far_apart_edits_get_their_own_hunks() {
+        let old : Vec<String> = (0..40).map(|x| x.to_string()).collect();
+        let mut new : Vec<String> = old.clone();
+        new[1] = "one".to_string();
+        new[38] = "thirty eight".to_string();
+        let diff : Vec<String> = round_trip(&old, &new);
+        assert_eq!(diff.iter().filter(|x| x.starts_with("@@")).count(), 2);
+    }
+
+    #[test]
+    fn too_many_changes_give_no_diff() {
+        let old : Vec<String> = (0..MAX_EDITS).map(|x| format!("old {}", x)).collect();
+        let new : Vec<String> = (0..MAX_EDITS).map(|x| format!("new {}", x)).collect();
+        assert!(unified_diff(&old, &new, "f.txt").is_none());
+    }
+
+    #[test]
+    fn a_patch_for_another_version_does_not_apply() {
+        let diff : Vec<String> = unified_diff(&lines("a\nb\nc"), &lines("a\nB\nc"), "f.txt").unwrap();
+        assert_eq!(apply_patch(&lines("a\nX\nc"), &diff), None);
+        assert_eq!(apply_patch(&[], &diff), None);
+    }
+
+    //Source files without a trailing newline still end in one on the target, every typed line
+    //ends with ENTER. So the lines are compared, and 'patch' gets the file as it was delivered.
+    #[test]
+    fn missing_trailing_newlines_round_trip_through_patch() {
+        let dir : PathBuf = env::temp_dir().join(format!("busbi-test-patch-{}", process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        write(dir.join("old"), "first\nsecond\nthird").unwrap();
+        write(dir.join("old-newline"), "first\nsecond\nthird\n").unwrap();
+        write(dir.join("new"), "zero\nfirst\nthird\nlast").unwrap();
+        let content : ContentOptions = ContentOptions::default();
+        let old : Vec<String> = delivered_lines(&dir.join("old"), &content).unwrap();
+        assert_eq!(old, delivered_lines(&dir.join("old-newline"), &content).unwrap());
+        let new : Vec<String> = delivered_lines(&dir.join("new"), &content).unwrap();
+        let diff : Vec<String> = round_trip(&old, &new);
+        //Only checked where patch is installed.
+        write(dir.join("target"), "first\nsecond\nthird\n").unwrap();
+        write(dir.join("f.txt.busbi-patch"), diff.iter().map(|x| format!("{}\n", x)).collect::<String>()).unwrap();
+        if let Ok(status) = Command::new("patch").args(["-f", "-s"]).arg(dir.join("target")).arg(dir.join("f.txt.busbi-patch")).status() {
+            assert!(status.success());
+            assert_eq!(read_to_string(dir.join("target")).unwrap(), "zero\nfirst\nthird\nlast\n");
+        }
+        remove_dir_all(&dir).unwrap();
+    }
+}
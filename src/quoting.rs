@@ -0,0 +1,106 @@
+// Every path busbi types into a shell on the target goes through here. File and directory names
+// can contain spaces, '$', quotes and other characters the target shell would otherwise act on.
+// The only thing that is supposed to be expanded on the target is a leading $HOME.
+
+const HOME_VAR : &str = "$HOME";
+
+//Quotes a path for the shell of the given target OS.
+pub fn quote_path(os_type: &str, path: &str) -> String {
+    if os_type.to_lowercase() == "windows" {
+        quote_powershell(path)
+    } else {
+        quote_unix(path)
+    }
+}
+
+//bash/zsh: $HOME stays in double quotes so it expands but is never split, everything after it is
+//put in single quotes when it contains anything but plain path characters. Double quotes are not
+//used for the rest as an interactive shell does history expansion on '!' inside them.
+pub fn quote_unix(path: &str) -> String {
+    match path.strip_prefix(HOME_VAR) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            format!("\"{}\"{}", HOME_VAR, quote_unix_word(rest))
+        },
+        _ => quote_unix_word(path),
+    }
+}
+
+//Single quotes a word unless it is made up of characters no shell treats specially.
+pub fn quote_unix_word(word: &str) -> String {
+    let is_plain : bool = word.chars().all(|c| c.is_ascii_alphanumeric() || "_-./+,:@%".contains(c));
+    if is_plain {
+        return word.to_string()
+    }
+    //A single quote can't appear inside single quotes, so close the quotes, add an escaped quote
+    //and open them again.
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+//PowerShell: the whole path goes in double quotes so $HOME expands, anything else that means
+//something inside double quotes gets escaped with a backtick. PowerShell also accepts the
+//typographic quotes as string delimiters so those are escaped as well.
+pub fn quote_powershell(path: &str) -> String {
+    let (home, rest) : (&str, &str) = match path.strip_prefix(HOME_VAR) {
+        Some(rest) if rest.is_empty() || rest.starts_with('\\') => (HOME_VAR, rest),
+        _ => ("", path),
+    };
+    let mut quoted : String = String::from("\"");
+    quoted.push_str(home);
+    for c in rest.chars() {
+        if matches!(c, '`' | '$' | '"' | '\u{201C}' | '\u{201D}' | '\u{201E}') {
+            quoted.push('`');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_unix_paths_are_left_alone() {
+        assert_eq!(quote_unix("dir/a_b-c.sh"), "dir/a_b-c.sh");
+        assert_eq!(quote_unix("$HOME"), "\"$HOME\"");
+        assert_eq!(quote_unix("$HOME/dir/a.sh"), "\"$HOME\"/dir/a.sh");
+    }
+
+    #[test]
+    fn unix_paths_with_special_characters_are_single_quoted() {
+        assert_eq!(quote_unix("$HOME/my dir/a b.sh"), "\"$HOME\"'/my dir/a b.sh'");
+        assert_eq!(quote_unix("$HOME/$PATH"), "\"$HOME\"'/$PATH'");
+        assert_eq!(quote_unix("$HOME/it's"), "\"$HOME\"'/it'\\''s'");
+        assert_eq!(quote_unix("say \"hi\""), "'say \"hi\"'");
+        assert_eq!(quote_unix("`id`"), "'`id`'");
+        assert_eq!(quote_unix("wow!"), "'wow!'");
+        assert_eq!(quote_unix("~/a"), "'~/a'");
+        assert_eq!(quote_unix("\u{2018}a\u{2019} \u{201C}b\u{201D}"), "'\u{2018}a\u{2019} \u{201C}b\u{201D}'");
+        //Only a leading $HOME followed by a separator is expanded.
+        assert_eq!(quote_unix("$HOMEDIR/a"), "'$HOMEDIR/a'");
+        assert_eq!(quote_unix_word("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn powershell_paths_are_double_quoted_with_escapes() {
+        assert_eq!(quote_powershell("$HOME"), "\"$HOME\"");
+        assert_eq!(quote_powershell("$HOME\\my dir\\a b.ps1"), "\"$HOME\\my dir\\a b.ps1\"");
+        assert_eq!(quote_powershell("$HOME\\$env:PATH"), "\"$HOME\\`$env:PATH\"");
+        assert_eq!(quote_powershell("$HOMEDIR"), "\"`$HOMEDIR\"");
+        assert_eq!(quote_powershell("it's"), "\"it's\"");
+        assert_eq!(quote_powershell("say \"hi\""), "\"say `\"hi`\"\"");
+        assert_eq!(quote_powershell("a`b"), "\"a``b\"");
+        assert_eq!(quote_powershell("wow!~"), "\"wow!~\"");
+        assert_eq!(quote_powershell("\u{201C}b\u{201D}\u{201E}"), "\"`\u{201C}b`\u{201D}`\u{201E}\"");
+        //Typographic single quotes only end single quoted strings.
+        assert_eq!(quote_powershell("\u{2018}a\u{2019}"), "\"\u{2018}a\u{2019}\"");
+    }
+
+    #[test]
+    fn paths_are_quoted_for_the_target_os() {
+        assert_eq!(quote_path("windows", "$HOME\\a b"), quote_powershell("$HOME\\a b"));
+        assert_eq!(quote_path("Windows", "a$b"), "\"a`$b\"");
+        assert_eq!(quote_path("unix", "$HOME/a b"), quote_unix("$HOME/a b"));
+    }
+}
@@ -0,0 +1,212 @@
+use std::fs::read_to_string;
+use std::path::{Component, Path};
+
+use crate::boilerplates::{ContentOptions, read_text_lines};
+use crate::help_msg::HelpMessage;
+use crate::ignore::{glob_match, relative_rule_path};
+
+// Looks for secrets in the files before they get typed, since the script ends up in plain text on
+// an SD card. Finds private keys, AWS keys, long random looking tokens and .env files. A line
+// containing 'busbi:allow' is never reported, whole files or single lines can also be allowed
+// through an allowlist file.
+
+const INLINE_ALLOW : &str = "busbi:allow";
+//Tokens shorter than this are too short to tell apart from normal words.
+const MIN_TOKEN_LENGTH : usize = 24;
+//Bits of Shannon entropy per character above which a token counts as random.
+const MIN_TOKEN_ENTROPY : f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecretsMode {
+    Fail,
+    Warn,
+    Off,
+}
+
+//A line of the allowlist, a glob for the source path optionally followed by ':' and a line number.
+#[derive(Debug, Clone)]
+struct AllowEntry {
+    pattern: String,
+    line: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SecretScan {
+    pub mode: SecretsMode,
+    allow: Vec<AllowEntry>,
+}
+
+struct Finding {
+    line: usize,
+    kind: &'static str,
+}
+
+impl Default for SecretScan {
+    fn default() -> SecretScan {
+        SecretScan { mode: SecretsMode::Fail, allow: Vec::new() }
+    }
+}
+
+impl SecretScan {
+    //Reads an allowlist with one 'path' or 'path:line' per line, paths are globs like in
+    //.busbiignore and are matched against the source path as given on the command line or relative
+    //to the source directory. Empty lines and lines starting with '#' are ignored.
+    pub fn read_allowlist(&mut self, allowlist_path: &Path) -> Result<(), HelpMessage> {
+        let contents : String = match read_to_string(allowlist_path) {
+            Ok(x) => x,
+            Err(e) => {println!("Could not read allowlist '{}', got error: {}", allowlist_path.display(), e); return Err(HelpMessage::FailedReadingAllowlist)},
+        };
+        for line in contents.lines() {
+            let line : &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry : AllowEntry = match line.rsplit_once(':') {
+                Some((pattern, number)) if number.parse::<usize>().is_ok() => AllowEntry { pattern: pattern.to_string(), line: number.parse().ok() },
+                _ => AllowEntry { pattern: line.to_string(), line: None },
+            };
+            self.allow.push(entry);
+        }
+        Ok(())
+    }
+
+    //Leading './' and other '.' components are dropped, so '-d ./dir' matches 'dir/config.env' too.
+    fn is_allowed(&self, path: &Path, root: &Path, line: usize) -> bool {
+        let given : String = path.components().filter(|x| *x != Component::CurDir).map(|x| x.as_os_str().to_string_lossy().into_owned()).collect::<Vec<String>>().join("/");
+        let relative : String = relative_rule_path(path, root);
+        let candidates : Vec<&String> = [&given, &relative].into_iter().filter(|x| !x.is_empty()).collect();
+        self.allow.iter().any(|x| candidates.iter().any(|y| glob_match(&x.pattern, y)) && x.line.is_none_or(|y| y == line))
+    }
+
+    //Scans every file that will be typed, with template placeholders already filled in so values
+    //from '--var' get checked too. Prints every finding, with '--secrets fail' any finding stops busbi.
+    //'root' is the source directory the allowlist paths can be relative to.
+    pub fn check(&self, paths: &[&Path], root: &Path, content: &ContentOptions) -> Result<(), HelpMessage> {
+        if self.mode == SecretsMode::Off {
+            return Ok(())
+        }
+        let mut found : usize = 0;
+        for path in paths {
+            //Files that aren't text can't be scanned line by line.
+            if read_to_string(path).is_err() {
+                continue;
+            }
+            let lines : Vec<String> = read_text_lines(path, content)?;
+            for finding in scan_lines(path, &lines) {
+                if self.is_allowed(path, root, finding.line) {
+                    continue;
+                }
+                println!("\x1b[33mPossible secret:\x1b[0m {}:{}: {}", path.display(), finding.line, finding.kind);
+                found += 1;
+            }
+        }
+        if found > 0 && self.mode == SecretsMode::Fail {
+            println!("Error: found {} possible secret(s), nothing was created. Put 'busbi:allow' on the line, add it to a\n\
+                '--secrets-allowlist' file or use '--secrets warn' if they are meant to be delivered.", found);
+            return Err(HelpMessage::SecretsFound)
+        }
+        Ok(())
+    }
+}
+
+fn scan_lines(path: &Path, lines: &[String]) -> Vec<Finding> {
+    let mut findings : Vec<Finding> = Vec::new();
+    let is_env_file : bool = is_env_file(path);
+    for (index, line) in lines.iter().enumerate() {
+        if line.contains(INLINE_ALLOW) {
+            continue;
+        }
+        let kind : Option<&'static str> = if line.contains("-----BEGIN") && line.contains("PRIVATE KEY") {
+            Some("private key")
+        } else if has_aws_access_key(line) {
+            Some("AWS access key ID")
+        } else if line.to_lowercase().contains("aws_secret_access_key") {
+            Some("AWS secret access key")
+        } else if is_env_file && is_env_assignment(line) {
+            Some("value in a .env file")
+        } else if has_random_token(line) {
+            Some("high entropy token")
+        } else {
+            None
+        };
+        if let Some(kind) = kind {
+            findings.push(Finding { line: index + 1, kind });
+        }
+    }
+    findings
+}
+
+//'.env', '.env.production' and the like, but not the examples that are meant to be shared.
+fn is_env_file(path: &Path) -> bool {
+    let name : String = path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+    (name == ".env" || name.starts_with(".env.")) && !["example", "sample", "template", "dist"].iter().any(|x| name.ends_with(x))
+}
+
+fn is_env_assignment(line: &str) -> bool {
+    let line : &str = line.trim();
+    match line.split_once('=') {
+        Some((name, value)) => !line.starts_with('#') && !name.trim().is_empty() && !value.trim().trim_matches(['"', '\'']).is_empty(),
+        None => false,
+    }
+}
+
+//AWS access key IDs are 20 characters, a known four letter prefix followed by upper case letters
+//and digits.
+fn has_aws_access_key(line: &str) -> bool {
+    const PREFIXES : [&str; 8] = ["AKIA", "ASIA", "AGPA", "AIDA", "AROA", "ANPA", "ANVA", "AIPA"];
+    line.split(|c: char| !c.is_ascii_alphanumeric())
+        .any(|x| x.len() == 20 && PREFIXES.iter().any(|p| x.starts_with(p)) && x.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()))
+}
+
+//Long runs of base64/hex like characters that mix letters and digits and look random. '/' splits
+//tokens even though base64 uses it, as paths would look random otherwise.
+fn has_random_token(line: &str) -> bool {
+    line.split(|c: char| !(c.is_ascii_alphanumeric() || "+=_-".contains(c)))
+        .filter(|x| x.len() >= MIN_TOKEN_LENGTH)
+        .any(|x| x.chars().any(|c| c.is_ascii_digit()) && x.chars().any(|c| c.is_ascii_alphabetic()) && shannon_entropy(x) >= MIN_TOKEN_ENTROPY)
+}
+
+fn shannon_entropy(token: &str) -> f64 {
+    let mut counts : [usize; 256] = [0; 256];
+    for byte in token.bytes() {
+        counts[byte as usize] += 1;
+    }
+    let length : f64 = token.len() as f64;
+    counts.iter().filter(|x| **x > 0).map(|x| {
+        let p : f64 = *x as f64 / length;
+        -p * p.log2()
+    }).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowing(patterns: &[&str]) -> SecretScan {
+        let allow : Vec<AllowEntry> = patterns.iter().map(|x| AllowEntry { pattern: x.to_string(), line: None }).collect();
+        SecretScan { mode: SecretsMode::Fail, allow }
+    }
+
+    #[test]
+    fn allowlist_ignores_leading_dot_components() {
+        let scan : SecretScan = allowing(&["dir/config.env"]);
+        assert!(scan.is_allowed(Path::new("./dir/config.env"), Path::new("./dir"), 1));
+        assert!(scan.is_allowed(Path::new("dir/config.env"), Path::new("dir"), 1));
+        assert!(!scan.is_allowed(Path::new("./other/config.env"), Path::new("./other"), 1));
+    }
+
+    #[test]
+    fn allowlist_matches_relative_to_the_source_directory() {
+        let scan : SecretScan = allowing(&["config.env", "sub/*.sh"]);
+        assert!(scan.is_allowed(Path::new("./dir/config.env"), Path::new("./dir"), 1));
+        assert!(scan.is_allowed(Path::new("/home/me/dir/sub/run.sh"), Path::new("/home/me/dir"), 1));
+        assert!(!scan.is_allowed(Path::new("./dir/sub/config.env"), Path::new("./dir"), 1));
+    }
+
+    #[test]
+    fn allowlist_line_numbers_must_match() {
+        let scan : SecretScan = SecretScan { mode: SecretsMode::Fail, allow: vec![AllowEntry { pattern: "a.sh".to_string(), line: Some(3) }] };
+        assert!(scan.is_allowed(Path::new("a.sh"), Path::new("a.sh"), 3));
+        assert!(!scan.is_allowed(Path::new("a.sh"), Path::new("a.sh"), 4));
+    }
+}
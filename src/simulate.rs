@@ -0,0 +1,929 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{OpenOptions, copy, create_dir_all, read, read_dir, read_link, read_to_string, remove_dir, remove_file, rename, write};
+use std::io::{self, Write};
+use std::mem::{replace, take};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+use crate::archive::{Unpacked, civil_from_unix, file_contents, unpack_tar_gz, unpack_zip};
+use crate::arguments::{Arguments, SourceEntry, parse_args_advanced};
+use crate::boilerplates::{BACKUP_DIR, BACKUP_LIST, ContentOptions, POWERSHELL_BACKUP_FUNCTION, POWERSHELL_RESTORE_FUNCTION, POWERSHELL_RESTORE_POLICY, POWERSHELL_RMDIR_FUNCTION, POWERSHELL_SAVE_POLICY, UNIX_BACKUP_FUNCTION, UNIX_RESTORE_FUNCTION, target_file_path};
+use crate::crypto::{decrypt_openssl, existing_passphrase};
+use crate::ducky::{Command, parse_script};
+use crate::encoding::base64_decode;
+use crate::handlers::{install_relative_path, resolve_target_path};
+use crate::manifest::{FileMapping, parse_mapping, read_manifest};
+use crate::metadata::Metadata;
+use crate::patch::{POWERSHELL_PATCH_FUNCTION, apply_patch};
+use crate::quoting::quote_powershell;
+
+// 'busbi simulate <file.txt>' replays a bad USB script the way the shell on the target would take
+// it and writes the files it creates into a directory on this machine, so payloads can be checked
+// without a Flipper. With '--source' the files are compared against the ones the script was made
+// from. Only the commands busbi itself generates are understood, anything else is listed as run.
+// $HOME on the target is the HOME directory inside the simulation directory, absolute paths are
+// placed below the simulation directory itself. 'busbi extract' and 'busbi explain' replay
+// scripts the same way.
+
+const HOME_DIR : &str = "HOME";
+const TEMP_DIR : &str = "TEMP";
+
+//Where the text typed at the moment ends up.
+enum Input {
+    //The launcher opened by GUI or WINDOWS, the typed text picks the program to start.
+    Launcher,
+    Shell,
+    //Lines a command on a Unix target reads until CTRL d.
+    Stdin(Reader, String),
+    //A PowerShell here-string, assigned to the variable once "'@" is typed.
+    HereString(String, Vec<String>),
+}
+
+//What a Unix command does with the lines it read.
+enum Reader {
+    Cat(String),
+    Base64(String),
+    //'busbi_file=$(cat)'
+    Variable(String),
+    Tar(String),
+    EncryptedTar(String),
+}
+
+//Everything a script does on the target, in the order it happens.
+#[derive(Debug, Clone)]
+pub enum Event {
+    Launched(String),
+    OpenedTerminal,
+    MadeDir(String),
+    //The target path and the size in bytes.
+    Wrote(String, u64),
+    Copied(String, String, u64),
+    Linked(String, String),
+    Removed(String),
+    //The target path and where it was moved or copied to.
+    BackedUp(String, String),
+    //The target path and the backup it was moved back from.
+    Restored(String, String),
+    SetMode(String, String),
+    Prompted(String),
+    ChangedPolicy(String),
+    //A script busbi delivered being run.
+    Executed(String),
+    //Any other command typed into the shell.
+    Ran(String),
+    Pressed(String),
+    Closed,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Launched(x) => write!(f, "Opened '{}' from the launcher.", x),
+            Event::OpenedTerminal => write!(f, "Opened a terminal."),
+            Event::MadeDir(x) => write!(f, "Made directory {}", x),
+            Event::Wrote(x, size) => write!(f, "Wrote {} ({} bytes)", x, size),
+            Event::Copied(x, y, size) => write!(f, "Copied {} to {} ({} bytes)", x, y, size),
+            Event::Linked(x, y) => write!(f, "Linked {} to {}", x, y),
+            Event::Removed(x) => write!(f, "Removed {}", x),
+            Event::SetMode(x, mode) => write!(f, "Set the mode of {} to {}", x, mode),
+            Event::Prompted(x) => write!(f, "Asked the operator for '{}'.", x),
+            Event::ChangedPolicy(x) => write!(f, "Changed the execution policy: {}", x),
+            Event::Executed(x) => write!(f, "Ran the script {}", x),
+            Event::Ran(x) => write!(f, "Ran: {}", x),
+            Event::BackedUp(x, y) => write!(f, "Backed up {} to {}", x, y),
+            Event::Restored(x, y) => write!(f, "Restored {} from {}", x, y),
+            Event::Pressed(x) => write!(f, "Pressed {}, which isn't simulated.", x),
+            Event::Closed => write!(f, "Closed the window."),
+        }
+    }
+}
+
+pub struct Simulator {
+    pub windows: bool,
+    pub root: PathBuf,
+    pub events: Vec<Event>,
+    input: Input,
+    //The line typed so far.
+    line: String,
+    vars: HashMap<String, String>,
+    bytes: HashMap<String, Vec<u8>>,
+    //Links on the target, relative to the simulation directory, and where they point to.
+    links: Vec<(PathBuf, PathBuf)>,
+    last: Option<Command>,
+    pub closed: bool,
+    verbose: bool,
+    pub errors: usize,
+}
+
+//Runs 'busbi simulate' and gives the exit code, 1 if the script failed somewhere or the files
+//don't match the sources.
+pub fn simulate_command(args: &[String]) -> i32 {
+    let mut script_path : Option<&str> = None;
+    let mut windows : Option<bool> = None;
+    let mut source : Option<&str> = None;
+    let mut out : Option<PathBuf> = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" | "--os" => match rest.next().map(|x| x.to_lowercase()).as_deref() {
+                Some("windows") => windows = Some(true),
+                Some("unix") => windows = Some(false),
+                _ => {println!("Error: '--os' needs 'windows' or 'unix'."); return 2},
+            },
+            "--source" => match rest.next() {
+                Some(x) => source = Some(x),
+                None => {println!("Error: '--source' needs the file or directory the script was made from."); return 2},
+            },
+            "--out" => match rest.next() {
+                Some(x) => out = Some(PathBuf::from(x)),
+                None => {println!("Error: '--out' needs a directory."); return 2},
+            },
+            x if script_path.is_none() => script_path = Some(x),
+            x => {println!("Error: unexpected argument '{}'.", x); return 2},
+        }
+    }
+    let script_path : &str = match script_path {
+        Some(x) => x,
+        None => {println!("Error: give the bad USB script to simulate, like 'busbi simulate script.txt --os unix'."); return 2},
+    };
+    let root : PathBuf = out.unwrap_or(env::temp_dir().join(format!("busbi-simulate-{}", process::id())));
+    let simulator : Simulator = match replay_script(Path::new(script_path), windows, &root, true) {
+        Ok(x) => x,
+        Err(code) => return code,
+    };
+    if !simulator.closed {
+        println!("The window stays open.");
+    }
+    let mut differences : usize = 0;
+    if let Some(x) = source {
+        //The header says how the script was built and so where every source file ends up.
+        let options : Vec<String> = read_to_string(script_path).ok().and_then(|y| Metadata::parse(&y))
+            .map(|y| unix_words(&y.options)).unwrap_or_default();
+        let layout : Layout = Layout::from_options(&options, Path::new(script_path));
+        differences = match rebuild_sources(&options, Path::new(x)) {
+            Ok((delivered, content)) => compare_sources(Path::new(x), &layout, &simulator, delivered.as_deref(), &content),
+            Err(e) => {println!("Error: {}, the files were not compared.", e); 1},
+        };
+    }
+    println!("Simulated files are in {}", simulator.root.display());
+    if simulator.errors > 0 || differences > 0 {1} else {0}
+}
+
+//Replays the script into 'root', which has to be empty or not exist yet. Without a target OS it is
+//guessed from the script, with 'verbose' every event is printed as it happens. The error is the
+//exit code for when the script can't be replayed at all.
+pub fn replay_script(script_path: &Path, windows: Option<bool>, root: &Path, verbose: bool) -> Result<Simulator, i32> {
+    let script : String = match read_to_string(script_path) {
+        Ok(x) => x,
+        Err(e) => {eprintln!("Could not read {}, got error: {}", script_path.display(), e); return Err(2)},
+    };
+    let commands : Vec<Command> = match parse_script(&script) {
+        Ok(x) => x,
+        Err((line, e)) => {eprintln!("Error: {}:{}: {}, the Flipper stops here. See 'busbi lint'.", script_path.display(), line, e); return Err(1)},
+    };
+    if read_dir(root).is_ok_and(|mut x| x.next().is_some()) {
+        eprintln!("Error: {} is not empty, give an empty or new directory to '--out'.", root.display());
+        return Err(2)
+    }
+    if let Err(e) = create_dir_all(root.join(HOME_DIR)).and(create_dir_all(root.join(TEMP_DIR))) {
+        eprintln!("Could not create {}, got error: {}", root.display(), e);
+        return Err(2)
+    }
+    let mut simulator = Simulator {
+        windows: windows.unwrap_or_else(|| is_windows_script(&commands)), root: root.to_path_buf(), events: Vec::new(),
+        input: Input::Shell, line: String::new(), vars: HashMap::new(), bytes: HashMap::new(), links: Vec::new(),
+        last: None, closed: false, verbose, errors: 0,
+    };
+    for command in &commands {
+        simulator.press(command);
+        if simulator.closed {
+            break;
+        }
+    }
+    match simulator.input {
+        Input::Stdin(..) => simulator.fail("the script ended while a command was still reading input, CTRL d is missing".to_string()),
+        Input::HereString(..) => simulator.fail("the script ended inside a here-string, \"'@\" is missing".to_string()),
+        _ => (),
+    }
+    Ok(simulator)
+}
+
+//Scripts for Windows open PowerShell with the WINDOWS key, busbi never uses it for Unix targets.
+fn is_windows_script(commands: &[Command]) -> bool {
+    commands.iter().any(|x| matches!(x, Command::Key(keys) if keys[0] == "WINDOWS"))
+}
+
+impl Simulator {
+    fn record(&mut self, event: Event) {
+        if self.verbose {
+            println!("{}", event);
+        }
+        if matches!(event, Event::Closed) {
+            self.closed = true;
+        }
+        self.events.push(event);
+    }
+
+    //On stderr like every other problem, so what 'busbi explain --format json' prints stays JSON.
+    fn fail(&mut self, message: String) {
+        eprintln!("\x1b[31merror\x1b[0m: {}", message);
+        self.errors += 1;
+    }
+
+    fn press(&mut self, command: &Command) {
+        match command {
+            Command::String(x) | Command::AltString(x) => self.line.push_str(x),
+            Command::StringLn(x) => {self.line.push_str(x); self.enter()},
+            Command::Repeat(times) => {
+                if let Some(last) = self.last.clone() {
+                    for _ in 0..*times {
+                        self.press(&last);
+                    }
+                }
+                return
+            },
+            Command::Key(keys) => match keys.iter().map(|x| x.as_str()).collect::<Vec<&str>>()[..] {
+                ["ENTER"] => self.enter(),
+                ["SPACE"] => self.line.push(' '),
+                ["TAB"] => self.line.push('\t'),
+                ["BACKSPACE"] => {self.line.pop();},
+                ["CTRL" | "CONTROL", "d"] => self.end_of_input(),
+                ["CTRL-ALT", "t"] => {self.record(Event::OpenedTerminal); self.input = Input::Shell},
+                ["GUI" | "WINDOWS", ..] => {self.line.clear(); self.input = Input::Launcher},
+                _ => self.record(Event::Pressed(keys.join(" "))),
+            },
+            _ => (),
+        }
+        self.last = Some(command.clone());
+    }
+
+    fn enter(&mut self) {
+        let line : String = take(&mut self.line);
+        match replace(&mut self.input, Input::Shell) {
+            Input::Launcher => self.record(Event::Launched(line)),
+            Input::Stdin(reader, mut text) => {
+                text.push_str(&line);
+                text.push('\n');
+                self.input = Input::Stdin(reader, text);
+            },
+            Input::HereString(name, lines) if line == "'@" => {self.vars.insert(name, lines.join("\n"));},
+            Input::HereString(name, mut lines) => {
+                lines.push(line);
+                self.input = Input::HereString(name, lines);
+            },
+            Input::Shell if self.windows => self.run_powershell(&line),
+            Input::Shell => self.run_unix(&line),
+        }
+    }
+
+    fn end_of_input(&mut self) {
+        match replace(&mut self.input, Input::Shell) {
+            Input::Stdin(reader, mut text) => {
+                text.push_str(&take(&mut self.line));
+                self.finish_reading(reader, text);
+            },
+            Input::Shell if !self.windows && self.line.is_empty() => {
+                eprintln!("CTRL d on an empty line closes the shell.");
+                self.record(Event::Closed);
+            },
+            other => self.input = other,
+        }
+    }
+
+    fn finish_reading(&mut self, reader: Reader, text: String) {
+        match reader {
+            Reader::Cat(path) => self.write_file(&path, text.as_bytes()),
+            Reader::Base64(path) => match base64_decode(&text) {
+                Some(x) => self.write_file(&path, &x),
+                None => self.fail(format!("base64 -d: the text for {} is not valid base64", path)),
+            },
+            //Command substitution drops every trailing newline.
+            Reader::Variable(name) => {self.vars.insert(name, text.trim_end_matches('\n').to_string());},
+            Reader::Tar(dir) => match base64_decode(&text) {
+                Some(x) => self.unpack(&dir, &x, false),
+                None => self.fail("base64 -d: the archive is not valid base64".to_string()),
+            },
+            Reader::EncryptedTar(dir) => match base64_decode(&text).and_then(|x| decrypt(&x)) {
+                Some(x) => self.unpack(&dir, &x, false),
+                None => self.fail("openssl: could not decrypt the archive, is the passphrase right?".to_string()),
+            },
+        }
+    }
+
+    //The value of a '$name' word, or the word itself.
+    fn value(&self, word: &str) -> String {
+        match word.strip_prefix('$').and_then(|x| self.vars.get(x)) {
+            Some(x) => x.clone(),
+            None => word.to_string(),
+        }
+    }
+
+    //Where a path on the target is inside the simulation directory. '..' is dropped so nothing can
+    //end up outside of it.
+    pub fn host_path(&self, target: &str) -> PathBuf {
+        let target : String = self.value(target);
+        let (mut path, rest) : (PathBuf, &str) = if let Some(rest) = target.strip_prefix("$HOME") {
+            (self.root.join(HOME_DIR), rest)
+        } else if let Some(rest) = target.strip_prefix("$env:TEMP") {
+            (self.root.join(TEMP_DIR), rest)
+        } else if self.windows && let Some((drive, rest)) = target.split_once(':') && drive.chars().count() == 1 {
+            (self.root.join(drive), rest)
+        } else if target.starts_with(['/', '\\']) {
+            (self.root.clone(), target.as_str())
+        } else {
+            (self.root.join(HOME_DIR), target.as_str())
+        };
+        let separators : &[char] = if self.windows {&['/', '\\']} else {&['/']};
+        for part in rest.split(separators) {
+            if !matches!(part, "" | "." | "..") {
+                path.push(part);
+            }
+        }
+        path
+    }
+
+    fn make_dir(&mut self, target: &str) {
+        match create_dir_all(self.host_path(target)) {
+            Ok(_) => self.record(Event::MadeDir(target.to_string())),
+            Err(e) => self.fail(format!("could not make directory {}: {}", target, e)),
+        }
+    }
+
+    //'quiet' is for 'rm -f' and the like, a file that is already gone counts as removed then.
+    fn remove(&mut self, command: &str, target: &str, quiet: bool) {
+        let target : String = self.value(target);
+        match remove_file(self.host_path(&target)) {
+            Ok(_) => self.record(Event::Removed(target)),
+            Err(e) if quiet && e.kind() == io::ErrorKind::NotFound => self.record(Event::Removed(target)),
+            Err(e) => self.fail(format!("{}: could not remove {}: {}", command, target, e)),
+        }
+    }
+
+    //Like the shell, the directory the file goes in has to exist already.
+    fn write_file(&mut self, target: &str, contents: &[u8]) {
+        let target : String = self.value(target);
+        match write(self.host_path(&target), contents) {
+            Ok(_) => self.record(Event::Wrote(target, contents.len() as u64)),
+            Err(e) => self.fail(format!("could not write {}: {}", target, e)),
+        }
+    }
+
+    //Moves a file that is already there into the backup directory like busbi_backup_file does, or
+    //copies it with 'keep'. See backup_boilerplate().
+    fn backup(&mut self, target: &str, name: &str, keep: bool) {
+        let target : String = self.value(target);
+        let from : PathBuf = self.host_path(&target);
+        if from.symlink_metadata().is_err() {
+            return
+        }
+        let separator : &str = if self.windows {"\\"} else {"/"};
+        let backup_dir : String = self.value("$busbi_backup");
+        let backup_target : String = format!("{}{}{}", backup_dir, separator, name);
+        let to : PathBuf = self.host_path(&backup_target);
+        let list : PathBuf = self.host_path(&format!("{}{}{}", backup_dir, separator, BACKUP_LIST));
+        let backed_up = create_dir_all(to.parent().unwrap_or(&self.root))
+            .and_then(|_| if keep {copy(&from, &to).map(|_| ())} else {rename(&from, &to)})
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&list))
+            .and_then(|mut x| writeln!(x, "{}", name));
+        match backed_up {
+            Ok(_) => self.record(Event::BackedUp(target, backup_target)),
+            Err(e) => self.fail(format!("could not back up {}: {}", target, e)),
+        }
+    }
+
+    //Moves a file back from the oldest backup directory of the script that has it, see
+    //restore_boilerplate().
+    fn restore(&mut self, target: &str, name: &str) {
+        let target : String = self.value(target);
+        let separator : &str = if self.windows {"\\"} else {"/"};
+        let backups : String = format!("$HOME{}{}", separator, BACKUP_DIR);
+        let prefix : String = format!("{}-", self.value("$busbi_backup_name"));
+        let mut dirs : Vec<String> = read_dir(self.host_path(&backups)).map(|x| x.filter_map(Result::ok)
+            .filter(|y| y.path().is_dir())
+            .map(|y| y.file_name().to_string_lossy().into_owned())
+            .filter(|y| y.starts_with(&prefix)).collect()).unwrap_or_default();
+        dirs.sort();
+        for dir in dirs {
+            let backup_target : String = format!("{}{}{}{}{}", backups, separator, dir, separator, name);
+            let from : PathBuf = self.host_path(&backup_target);
+            if from.symlink_metadata().is_err() {
+                continue
+            }
+            let to : PathBuf = self.host_path(&target);
+            match create_dir_all(to.parent().unwrap_or(&self.root)).and_then(|_| rename(&from, &to)) {
+                Ok(_) => self.record(Event::Restored(target, backup_target)),
+                Err(e) => self.fail(format!("could not restore {}: {}", target, e)),
+            }
+            return
+        }
+    }
+
+    //Removes a directory only when it is empty, like 'rmdir' does.
+    fn remove_dir(&mut self, target: &str) {
+        let target : String = self.value(target);
+        let dir : PathBuf = self.host_path(&target);
+        if read_dir(&dir).map(|mut x| x.next().is_none()).unwrap_or(false) && remove_dir(&dir).is_ok() {
+            self.record(Event::Removed(target));
+        }
+    }
+
+    //Applies a diff typed for '--patch' like the target does, see patch.rs. A file that isn't the
+    //version the diff was made for is left as it is.
+    fn patch_file(&mut self, target: &str, diff: &str) {
+        let target : String = self.value(target);
+        let old : Vec<String> = match read_to_string(self.host_path(&target)) {
+            Ok(x) => x.lines().map(|y| y.to_string()).collect(),
+            Err(e) => return self.fail(format!("could not patch {}: {}", target, e)),
+        };
+        let diff : Vec<String> = diff.lines().map(|x| x.to_string()).collect();
+        match apply_patch(&old, &diff) {
+            Some(new) => {
+                let contents : String = new.iter().map(|x| format!("{}\n", x)).collect();
+                self.write_file(&target, contents.as_bytes());
+            },
+            None => self.fail(format!("could not patch {}, it is not the version the patch was made for", target)),
+        }
+    }
+
+    fn copy_file(&mut self, from: &str, to: &str) {
+        match copy(self.host_path(from), self.host_path(to)) {
+            Ok(x) => self.record(Event::Copied(from.to_string(), to.to_string(), x)),
+            Err(e) => self.fail(format!("could not copy {} to {}: {}", from, to, e)),
+        }
+    }
+
+    fn unpack(&mut self, target_dir: &str, archive: &[u8], zip: bool) {
+        let dir : PathBuf = self.host_path(target_dir);
+        let unpacked = if zip {unpack_zip(archive, &dir)} else {unpack_tar_gz(archive, &dir)};
+        match unpacked {
+            Ok(entries) => {
+                let separator : &str = if self.windows {"\\"} else {"/"};
+                let target = |path: &Path| -> String {
+                    let parts : Vec<String> = path.components().map(|x| x.as_os_str().to_string_lossy().into_owned()).collect();
+                    format!("{}{}{}", target_dir, separator, parts.join(separator))
+                };
+                for entry in entries {
+                    match entry {
+                        Unpacked::File(path) => {
+                            let size : u64 = dir.join(&path).metadata().map(|x| x.len()).unwrap_or(0);
+                            self.record(Event::Wrote(target(&path), size));
+                        },
+                        Unpacked::Dir(path) => self.record(Event::MadeDir(target(&path))),
+                        Unpacked::Symlink(path, link_target) => {
+                            self.record(Event::Linked(target(&path), link_target.to_string_lossy().into_owned()));
+                            let link : PathBuf = dir.join(path).strip_prefix(&self.root).map(|x| x.to_path_buf()).unwrap_or_default();
+                            self.links.push((link, link_target));
+                        },
+                    }
+                }
+            },
+            Err(e) => self.fail(format!("could not unpack the archive into {}: {}", target_dir, e)),
+        }
+    }
+
+    //A prompt is answered with the placeholder it came from, so the file matches its source.
+    fn answer_prompt(&mut self, name: &str) {
+        self.record(Event::Prompted(name.to_string()));
+        self.vars.insert(format!("busbi_{}", name), format!("{{{{ prompt:{} }}}}", name));
+    }
+
+    fn run_unix(&mut self, line: &str) {
+        if [UNIX_BACKUP_FUNCTION, UNIX_RESTORE_FUNCTION].concat().contains(&line) || line.starts_with("[ -f \"$busbi_backup\"") {
+            return
+        }
+        if let Some(name) = line.strip_prefix("busbi_backup_name=") {
+            self.vars.insert("busbi_backup_name".to_string(), unix_words(name).concat());
+            return
+        }
+        if let Some(rest) = line.strip_prefix("busbi_backup=").and_then(|x| x.strip_suffix("-$(date +%Y%m%d-%H%M%S)")) {
+            self.vars.insert("busbi_backup".to_string(), format!("{}-{}", unix_words(rest).concat(), backup_time()));
+            return
+        }
+        if line == "busbi_file=$(cat)" {
+            self.input = Input::Stdin(Reader::Variable("busbi_file".to_string()), String::new());
+            return
+        }
+        if let Some(rest) = line.strip_prefix("busbi_file=${busbi_file//") && let Some((marker, name)) = rest.split_once("/\"$") {
+            let value : String = self.vars.get(name.trim_end_matches("\"}")).cloned().unwrap_or_default();
+            let file : String = self.vars.get("busbi_file").cloned().unwrap_or_default().replace(marker, &value);
+            self.vars.insert("busbi_file".to_string(), file);
+            return
+        }
+        if let Some(rest) = line.strip_prefix("printf '%s\\n' \"$busbi_file\" > ") {
+            let contents : String = format!("{}\n", self.vars.get("busbi_file").cloned().unwrap_or_default());
+            self.write_file(&unix_words(rest).concat(), contents.as_bytes());
+            return
+        }
+        if let Some((_question, read)) = line.split_once("; read -rs busbi_") && let Some((name, _echo)) = read.split_once(';') {
+            self.answer_prompt(name);
+            return
+        }
+        let words : Vec<String> = unix_words(line);
+        match words.iter().map(|x| x.as_str()).collect::<Vec<&str>>()[..] {
+            ["mkdir", "-p", path] | ["mkdir", path] => self.make_dir(path),
+            ["cat", ">", path] => self.input = Input::Stdin(Reader::Cat(path.to_string()), String::new()),
+            ["base64", "-d", ">", path] => self.input = Input::Stdin(Reader::Base64(path.to_string()), String::new()),
+            ["base64", "-d", "|", "tar", "xzf", "-", "-C", dir] => self.input = Input::Stdin(Reader::Tar(dir.to_string()), String::new()),
+            ["base64", "-d", "|", "openssl", .., "tar", "xzf", "-", "-C", dir] => self.input = Input::Stdin(Reader::EncryptedTar(dir.to_string()), String::new()),
+            ["chmod", mode, path] => match self.host_path(path).exists() {
+                true => self.record(Event::SetMode(path.to_string(), mode.to_string())),
+                false => self.fail(format!("chmod: {} does not exist", path)),
+            },
+            ["cp", from, to] => self.copy_file(from, to),
+            ["rm", "-f", path] => self.remove("rm", path, true),
+            ["busbi_backup_file", path, name] => self.backup(path, name, false),
+            ["busbi_backup_file", path, name, "copy"] => self.backup(path, name, true),
+            ["busbi_restore_file", path, name] => self.restore(path, name),
+            ["rmdir", path, "2>/dev/null"] => self.remove_dir(path),
+            ["patch", "--dry-run", "-f", "-s", path, patch_path, ..] => match read_to_string(self.host_path(patch_path)) {
+                Ok(x) => self.patch_file(path, &x),
+                Err(e) => self.fail(format!("patch: could not read {}: {}", patch_path, e)),
+            },
+            ["ln", "-sfn", link_target, link] => {
+                let link_path : PathBuf = self.host_path(link).strip_prefix(&self.root).map(|x| x.to_path_buf()).unwrap_or_default();
+                self.record(Event::Linked(link.to_string(), link_target.to_string()));
+                self.links.push((link_path, PathBuf::from(link_target)));
+            },
+            ["export", _] | ["unset", _] => (),
+            ["exit"] => self.record(Event::Closed),
+            [path] if self.host_path(path).is_file() => self.record(Event::Executed(path.to_string())),
+            _ => self.record(Event::Ran(line.to_string())),
+        }
+    }
+
+    fn run_powershell(&mut self, line: &str) {
+        if [POWERSHELL_PATCH_FUNCTION, POWERSHELL_BACKUP_FUNCTION, POWERSHELL_RESTORE_FUNCTION, POWERSHELL_RMDIR_FUNCTION].concat().contains(&line)
+            || line.starts_with("if (Test-Path -LiteralPath \"$busbi_backup\\") {
+            return
+        }
+        if let Some(name) = line.strip_prefix("$busbi_backup_name = ") {
+            self.vars.insert("busbi_backup_name".to_string(), powershell_words(name).concat());
+            return
+        }
+        //The execution policy from before '-x' is kept in a file, the simulated one is Undefined.
+        if let Some(path) = line.strip_prefix("$busbi_policy = ") {
+            self.vars.insert("busbi_policy".to_string(), powershell_words(path).concat());
+            return
+        }
+        if line == POWERSHELL_SAVE_POLICY {
+            let path : String = self.value("$busbi_policy");
+            if !self.host_path(&path).exists() {
+                let _ = self.host_path(&path).parent().map(create_dir_all);
+                self.write_file(&path, b"Undefined\n");
+            }
+            return
+        }
+        if line == POWERSHELL_RESTORE_POLICY {
+            let path : String = self.value("$busbi_policy");
+            if self.host_path(&path).exists() {
+                self.record(Event::ChangedPolicy(line.replace("$busbi_policy", &quote_powershell(&path))));
+                self.remove("Remove-Item", &path, false);
+            }
+            return
+        }
+        if let Some(dir) = line.strip_prefix("$busbi_backup = ").and_then(|x| x.strip_suffix(" + (Get-Date -Format '-yyyyMMdd-HHmmss')")) {
+            self.vars.insert("busbi_backup".to_string(), format!("{}-{}", powershell_words(dir).concat(), backup_time()));
+            return
+        }
+        if let Some(name) = line.strip_prefix('$').and_then(|x| x.strip_suffix(" = @'")) {
+            self.input = Input::HereString(name.to_string(), Vec::new());
+            return
+        }
+        if let Some(rest) = line.strip_prefix("$file = $file.Replace('") && let Some((marker, name)) = rest.split_once("', $") {
+            let value : String = self.vars.get(name.trim_end_matches(')')).cloned().unwrap_or_default();
+            let file : String = self.vars.get("file").cloned().unwrap_or_default().replace(marker, &value);
+            self.vars.insert("file".to_string(), file);
+            return
+        }
+        if let Some(rest) = line.strip_prefix("$busbi_") && rest.contains("Read-Host") && let Some((name, _read)) = rest.split_once(' ') {
+            self.answer_prompt(name);
+            return
+        }
+        if let Some(rest) = line.strip_prefix("[IO.File]::WriteAllBytes(") && let Some((path, contents)) = rest.split_once(", ") {
+            let path : String = powershell_words(path).concat();
+            let decoded : Option<Vec<u8>> = match contents.trim_end_matches(')') {
+                "[Convert]::FromBase64String($file" => base64_decode(&self.value("$file")),
+                x => x.strip_prefix('$').and_then(|y| self.bytes.get(y)).cloned(),
+            };
+            match decoded {
+                Some(x) => self.write_file(&path, &x),
+                None => self.fail(format!("WriteAllBytes: nothing valid to write to {}", path)),
+            }
+            return
+        }
+        if line == "$bytes = [Convert]::FromBase64String($archive)" {
+            match base64_decode(&self.value("$archive")) {
+                Some(x) => {self.bytes.insert("bytes".to_string(), x);},
+                None => self.fail("FromBase64String: the archive is not valid base64".to_string()),
+            }
+            return
+        }
+        if line.starts_with("$bytes = $aes.CreateDecryptor()") {
+            match self.bytes.get("bytes").and_then(|x| decrypt(x)) {
+                Some(x) => {self.bytes.insert("bytes".to_string(), x);},
+                None => self.fail("could not decrypt the archive, is the passphrase right?".to_string()),
+            }
+            return
+        }
+        if line.starts_with("$kdf = ") || line.starts_with("$aes = ") {
+            return
+        }
+        let words : Vec<String> = powershell_words(line);
+        match words.iter().map(|x| x.as_str()).collect::<Vec<&str>>()[..] {
+            ["New-Item", "-ItemType", "Directory", "-Path", path, "-Force"] | ["New-Item", "-Path", path, "-Type", "Directory"] => self.make_dir(path),
+            ["Set-Content", "-LiteralPath", path, "-Value", value] => {
+                let contents : String = format!("{}\n", self.value(value));
+                self.write_file(path, contents.as_bytes());
+            },
+            ["Copy-Item", "-LiteralPath", from, "-Destination", to, "-Force"] => self.copy_file(from, to),
+            ["$archivePath", "=", "Join-Path", dir, name] => {self.vars.insert("archivePath".to_string(), format!("{}\\{}", dir, name));},
+            ["Expand-Archive", "-LiteralPath", archive, "-DestinationPath", dir, "-Force"] => match read(self.host_path(archive)) {
+                Ok(x) => self.unpack(dir, &x, true),
+                Err(e) => self.fail(format!("Expand-Archive: could not read {}: {}", self.value(archive), e)),
+            },
+            ["Remove-Item", "-LiteralPath", path] => self.remove("Remove-Item", path, false),
+            ["Remove-Item", "-LiteralPath", path, "-Force", "-ErrorAction", "SilentlyContinue"] => self.remove("Remove-Item", path, true),
+            ["Remove-Variable", _] => (),
+            ["busbi_patch", path, "$file"] => self.patch_file(path, &self.value("$file")),
+            ["busbi_backup_file", path, name] => self.backup(path, name, false),
+            ["busbi_backup_file", path, name, "$true"] => self.backup(path, name, true),
+            ["busbi_restore_file", path, name] => self.restore(path, name),
+            ["busbi_rmdir", path] => self.remove_dir(path),
+            ["$code", "=", "Get-Content", "-LiteralPath", path, "-Raw"] => {self.vars.insert("code".to_string(), path.to_string());},
+            ["Invoke-Expression", "$code"] => self.record(Event::Executed(self.value("$code"))),
+            ["Set-ExecutionPolicy", ..] => self.record(Event::ChangedPolicy(line.to_string())),
+            ["exit"] => self.record(Event::Closed),
+            _ => self.record(Event::Ran(line.to_string())),
+        }
+    }
+}
+
+//Stands in for the time the target puts in the name of the backup directory.
+fn backup_time() -> String {
+    let seconds : u64 = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+    let (year, month, day, hour, minute, second) = civil_from_unix(seconds);
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, hour, minute, second)
+}
+
+//Decrypts with the archive passphrase, from BUSBI_PASSPHRASE or asked for once.
+fn decrypt(data: &[u8]) -> Option<Vec<u8>> {
+    decrypt_openssl(data, &existing_passphrase().ok()?)
+}
+
+//Splits a line the way bash does, for the quoting quote_unix() produces.
+fn unix_words(line: &str) -> Vec<String> {
+    let mut words : Vec<String> = Vec::new();
+    let mut word : Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {words.extend(word.take()); continue},
+            '\'' => word.get_or_insert_default().extend(chars.by_ref().take_while(|x| *x != '\'')),
+            '"' => {
+                let quoted : &mut String = word.get_or_insert_default();
+                while let Some(x) = chars.next() {
+                    match x {
+                        '"' => break,
+                        '\\' => quoted.extend(chars.next()),
+                        _ => quoted.push(x),
+                    }
+                }
+            },
+            '\\' => word.get_or_insert_default().extend(chars.next()),
+            _ => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+//Splits a line the way PowerShell does, for the quoting quote_powershell() produces.
+fn powershell_words(line: &str) -> Vec<String> {
+    let mut words : Vec<String> = Vec::new();
+    let mut word : Option<String> = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {words.extend(word.take()); continue},
+            '\'' => {
+                let quoted : &mut String = word.get_or_insert_default();
+                while let Some(x) = chars.next() {
+                    match x {
+                        '\'' if chars.peek() == Some(&'\'') => {chars.next(); quoted.push('\'')},
+                        '\'' => break,
+                        _ => quoted.push(x),
+                    }
+                }
+            },
+            '"' => {
+                let quoted : &mut String = word.get_or_insert_default();
+                while let Some(x) = chars.next() {
+                    match x {
+                        '"' => break,
+                        '`' => quoted.extend(chars.next()),
+                        _ => quoted.push(x),
+                    }
+                }
+            },
+            _ => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+//Every file and link below the directory, relative to it.
+fn walk(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>, links: &mut Vec<(PathBuf, PathBuf)>) {
+    let Ok(entries) = read_dir(dir.join(relative)) else {return};
+    for entry in entries.flatten() {
+        let path : PathBuf = relative.join(entry.file_name());
+        match entry.file_type() {
+            Ok(x) if x.is_symlink() => links.push((path, read_link(entry.path()).unwrap_or_default())),
+            Ok(x) if x.is_dir() => walk(dir, &path, files, links),
+            Ok(_) => files.push(path),
+            Err(_e) => (),
+        }
+    }
+}
+
+//How the simulated script puts source files on the target.
+enum Layout {
+    //'-d' with the source directory as given, the script name and '--keep-prefix'.
+    Directory(String, String, bool),
+    //'-m' with the directory the scripts went in.
+    Many(String),
+    Mapped(Vec<FileMapping>),
+    Single,
+}
+
+impl Layout {
+    //From the options in the header of the script. A script without one is taken to be a '-d'
+    //build with the name it has now.
+    fn from_options(options: &[String], script_path: &Path) -> Layout {
+        if options.is_empty() {
+            let name : String = script_path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+            return Layout::Directory(String::new(), name, false)
+        }
+        let keep_prefix : bool = options.iter().any(|x| x == "--keep-prefix");
+        let install_dir : Option<&String> = options.iter().position(|x| x == "--install-dir").and_then(|x| options.get(x + 1));
+        let mut mappings : Vec<FileMapping> = Vec::new();
+        let mut rest = options.iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "-d" | "--directory" => if let (Some(source), Some(dest)) = (rest.next(), rest.next()) {
+                    return Layout::Directory(source.clone(), install_dir.unwrap_or(dest).clone(), keep_prefix)
+                },
+                "-m" | "--many" => if let (Some(_source), Some(dest)) = (rest.next(), rest.next()) {
+                    return Layout::Many(dest.clone())
+                },
+                "--map" => mappings.extend(rest.next().and_then(|x| parse_mapping(x).ok())),
+                "--manifest" => mappings.extend(rest.next().and_then(|x| read_manifest(Path::new(x)).ok()).unwrap_or_default()),
+                _ => (),
+            }
+        }
+        if mappings.is_empty() {Layout::Single} else {Layout::Mapped(mappings)}
+    }
+
+    //The target path of the source file at 'relative' below 'source_dir', like the script has it.
+    fn target(&self, os_type: &str, source_dir: &Path, relative: &Path) -> Option<String> {
+        match self {
+            Layout::Directory(source, dest, keep_prefix) => {
+                let file : PathBuf = Path::new(source).join(relative);
+                let install_path : PathBuf = install_relative_path(&file, Path::new(source), *keep_prefix);
+                Some(target_file_path(os_type, &file, &install_path, dest, true, false))
+            },
+            Layout::Many(dest) => Some(target_file_path(os_type, relative, relative, dest, false, true)),
+            Layout::Mapped(mappings) => {
+                //Only the normal parts of the paths are compared, so './a' is the same as 'a'.
+                let file : PathBuf = install_relative_path(&source_dir.join(relative), Path::new(""), false);
+                mappings.iter().find(|x| install_relative_path(&x.source, Path::new(""), false) == file)
+                    .map(|x| resolve_target_path(os_type, &x.target))
+            },
+            Layout::Single => Some(target_file_path(os_type, relative, relative, "", false, false)),
+        }
+    }
+}
+
+//Parses the options from the header again with 'source' in place of the source directory they
+//name, so files are compared the way the build delivered them. Gives the paths of the entries a
+//'-d' or '-m' build delivered, after its ignore rules, and what '--minify' and the templates do to
+//their content.
+fn rebuild_sources(options: &[String], source: &Path) -> Result<(Option<Vec<PathBuf>>, ContentOptions), String> {
+    if options.is_empty() {
+        return Ok((None, ContentOptions::default()))
+    }
+    if options.iter().any(|x| x == "--var") {
+        return Err("the script was built with '--var' and its values are left out of the header, so the placeholders can't be filled in like the build did".to_string())
+    }
+    let mut args : Vec<String> = vec!["busbi".to_string()];
+    let mut rest = options.iter();
+    let mut walked : bool = false;
+    while let Some(arg) = rest.next() {
+        args.push(arg.clone());
+        if ["-d", "--directory", "-m", "--many"].contains(&arg.as_str()) && rest.next().is_some() {
+            args.push(source.display().to_string());
+            walked = true;
+        }
+    }
+    let arguments : Arguments = parse_args_advanced(&args).map_err(|e| format!("the options of the script don't work with '{}', got {:?}", source.display(), e))?;
+    let delivered : Option<Vec<PathBuf>> = walked.then(|| arguments.list_files.iter().filter_map(|x| match x {
+        SourceEntry::File(path) | SourceEntry::Binary(path) | SourceEntry::Symlink(path, _) => path.strip_prefix(source).ok().map(|y| y.to_path_buf()),
+        SourceEntry::EmptyDir(_) => None,
+    }).collect());
+    Ok((delivered, arguments.content))
+}
+
+//Compares every source file the build delivered with the simulated file at its target path, as it
+//was delivered, and gives the number of files that are missing, different or there without a source.
+fn compare_sources(source: &Path, layout: &Layout, simulator: &Simulator, delivered: Option<&[PathBuf]>, content: &ContentOptions) -> usize {
+    let root : &Path = &simulator.root;
+    let mut sources : Vec<PathBuf> = Vec::new();
+    let mut source_links : Vec<(PathBuf, PathBuf)> = Vec::new();
+    let source_dir : &Path = if source.is_dir() {
+        walk(source, Path::new(""), &mut sources, &mut source_links);
+        source
+    } else {
+        sources.extend(source.file_name().map(PathBuf::from));
+        source.parent().unwrap_or(Path::new(""))
+    };
+    let mut simulated : Vec<PathBuf> = Vec::new();
+    walk(root, Path::new(""), &mut simulated, &mut Vec::new());
+    //Files the ignore rules of the build left out are not expected on the target.
+    if let Some(delivered) = delivered {
+        sources.retain(|x| delivered.contains(x));
+        source_links.retain(|x| delivered.contains(&x.0));
+    }
+    sources.sort();
+    source_links.sort();
+    simulated.sort();
+    let os_type : &str = if simulator.windows {"windows"} else {"unix"};
+    //Where a source is inside the simulation directory.
+    let target_of = |relative: &Path| -> Option<PathBuf> {
+        let target : String = layout.target(os_type, source_dir, relative)?;
+        simulator.host_path(&target).strip_prefix(root).ok().map(|x| x.to_path_buf())
+    };
+    let mut accounted : Vec<PathBuf> = Vec::new();
+
+    println!("Comparing with {}:", source.display());
+    let mut differences : usize = 0;
+    for relative in &sources {
+        let found : Option<PathBuf> = target_of(relative).filter(|x| simulated.contains(x));
+        let Some(found) = found else {
+            println!("  \x1b[31mmissing\x1b[0m   {}", relative.display());
+            differences += 1;
+            continue;
+        };
+        let expected : Vec<u8> = file_contents(&source_dir.join(relative), content).unwrap_or_default();
+        let actual : Vec<u8> = read(root.join(&found)).unwrap_or_default();
+        if expected == actual {
+            println!("  same      {}", relative.display());
+        } else {
+            println!("  \x1b[31mdifferent\x1b[0m {} ({})", relative.display(), first_difference(&expected, &actual));
+            differences += 1;
+        }
+        accounted.push(found);
+    }
+    for (relative, link_target) in &source_links {
+        let path : Option<PathBuf> = target_of(relative);
+        match simulator.links.iter().find(|x| Some(&x.0) == path.as_ref()) {
+            Some((_path, x)) if x == link_target => println!("  same      {} -> {}", relative.display(), link_target.display()),
+            Some((_path, x)) => {println!("  \x1b[31mdifferent\x1b[0m {} points to {}", relative.display(), x.display()); differences += 1},
+            //Windows targets get a copy of what the link points to.
+            None => match path.filter(|x| simulated.contains(x)) {
+                Some(x) => {
+                    if read(source_dir.join(relative)).ok() == read(root.join(&x)).ok() {
+                        println!("  same      {} (copied)", relative.display());
+                    } else {
+                        println!("  \x1b[31mdifferent\x1b[0m {} (copied)", relative.display());
+                        differences += 1;
+                    }
+                    accounted.push(x);
+                },
+                None => {println!("  \x1b[31mmissing\x1b[0m   {} -> {}", relative.display(), link_target.display()); differences += 1},
+            },
+        }
+    }
+    //Backups and the saved execution policy are the script's own, not files it delivers.
+    let backups : PathBuf = Path::new(HOME_DIR).join(BACKUP_DIR);
+    let mut extra : usize = 0;
+    for path in simulated.iter().filter(|x| !accounted.contains(x) && !x.starts_with(&backups)) {
+        println!("  \x1b[31mextra\x1b[0m     {}", path.display());
+        extra += 1;
+    }
+    println!("{} of {} source file(s) missing or different, {} file(s) without a source.", differences, sources.len() + source_links.len(), extra);
+    differences + extra
+}
+
+fn first_difference(expected: &[u8], actual: &[u8]) -> String {
+    let position : usize = expected.iter().zip(actual).take_while(|(x, y)| x == y).count();
+    let line : usize = expected[..position].iter().filter(|x| **x == b'\n').count() + 1;
+    if position == expected.len().min(actual.len()) {
+        format!("{} bytes instead of {}, from line {}", actual.len(), expected.len(), line)
+    } else {
+        format!("first difference on line {}", line)
+    }
+}
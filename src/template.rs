@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::help_msg::HelpMessage;
+
+// Build-time templates, so the same config can be delivered to many machines with small
+// differences. A '{{ name }}' placeholder in a text file is replaced before the file is typed,
+// with the value from '--var', then from '--vars' files and last from the environment of the
+// machine busbi runs on. A placeholder without a value stops busbi, a '\{{' is typed as a plain
+// '{{'. A '{{ prompt:name }}' is never filled in here, the operator types its value on the target
+// so secrets don't end up in the script on the Flipper.
+
+const OPEN : &str = "{{";
+const CLOSE : &str = "}}";
+const ESCAPED_OPEN : &str = "\\{{";
+const PROMPT_PREFIX : &str = "prompt:";
+
+//The values placeholders can be filled with.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateVars {
+    //Given with '--var', these win over everything else.
+    cli: HashMap<String, String>,
+    //Read from '--vars' files, a later file wins over an earlier one.
+    file: HashMap<String, String>,
+}
+
+impl TemplateVars {
+    //Parses the value given to '--var', which looks like 'proxy_host=10.0.0.1'.
+    pub fn set(&mut self, value: &str) -> Result<(), HelpMessage> {
+        let (name, var_value) : (String, String) = parse_assignment(value)?;
+        self.cli.insert(name, var_value);
+        Ok(())
+    }
+
+    //Reads a vars file with one 'name = value' per line. Empty lines and lines starting with '#'
+    //are ignored.
+    pub fn read_file(&mut self, vars_path: &Path) -> Result<(), HelpMessage> {
+        let contents : String = match read_to_string(vars_path) {
+            Ok(x) => x,
+            Err(e) => {println!("Could not read vars file '{}', got error: {}", vars_path.display(), e); return Err(HelpMessage::FailedReadingVars)},
+        };
+        for (number, line) in contents.lines().enumerate() {
+            let line : &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_assignment(line) {
+                Ok((name, var_value)) => {self.file.insert(name, var_value);},
+                Err(e) => {println!("Vars file error on line {}.", number + 1); return Err(e)},
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        self.cli.get(name).or(self.file.get(name)).cloned().or_else(|| env::var(name).ok())
+    }
+}
+
+fn parse_assignment(value: &str) -> Result<(String, String), HelpMessage> {
+    let (name, var_value) = match value.split_once('=') {
+        Some(x) => x,
+        None => {println!("Error: '{}' is not a valid variable, use 'name=value'.", value); return Err(HelpMessage::InvalidVar)},
+    };
+    let name : &str = name.trim();
+    if !is_valid_name(name) {
+        println!("Error: '{}' is not a valid variable name, use letters, digits, '_', '-' and '.'.", name);
+        return Err(HelpMessage::InvalidVar)
+    }
+    Ok((name.to_string(), var_value.trim().to_string()))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+}
+
+//A line split up into plain text and placeholders.
+enum Piece<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    //'{{ prompt:name }}', asked for on the target when the script runs.
+    Prompt(&'a str),
+}
+
+//Splits a line at its placeholders. The error says what is wrong with the line.
+fn split_line(line: &str) -> Result<Vec<Piece<'_>>, String> {
+    let mut pieces : Vec<Piece> = Vec::new();
+    let mut rest : &str = line;
+    loop {
+        let escaped : Option<usize> = rest.find(ESCAPED_OPEN);
+        let open : Option<usize> = rest.find(OPEN);
+        match (escaped, open) {
+            //The backslash comes right before the braces, so an escape is always found first.
+            (Some(e), Some(o)) if e < o => {
+                pieces.push(Piece::Text(&rest[..e]));
+                pieces.push(Piece::Text(OPEN));
+                rest = &rest[e + ESCAPED_OPEN.len()..];
+            },
+            (_, Some(o)) => {
+                pieces.push(Piece::Text(&rest[..o]));
+                let after_open : &str = &rest[o + OPEN.len()..];
+                let close : usize = match after_open.find(CLOSE) {
+                    Some(x) => x,
+                    None => return Err(format!("'{}' is never closed with '{}', use '\\{{{{' for literal braces", OPEN, CLOSE)),
+                };
+                let name : &str = after_open[..close].trim();
+                if let Some(prompt) = name.strip_prefix(PROMPT_PREFIX) {
+                    let prompt : &str = prompt.trim();
+                    //The name becomes part of a shell variable on the target.
+                    if prompt.is_empty() || !prompt.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                        return Err(format!("'{}' is not a valid prompt name, use letters, digits and '_'", prompt))
+                    }
+                    pieces.push(Piece::Prompt(prompt));
+                } else if is_valid_name(name) {
+                    pieces.push(Piece::Var(name));
+                } else {
+                    return Err(format!("'{}' is not a valid variable name, use '\\{{{{' for literal braces", name))
+                }
+                rest = &after_open[close + CLOSE.len()..];
+            },
+            (_, None) => {
+                pieces.push(Piece::Text(rest));
+                return Ok(pieces)
+            },
+        }
+    }
+}
+
+//Fills in every placeholder of a single line. Prompts are left as a marker that gets replaced on
+//the target, see prompt_marker().
+pub fn render_line(line: &str, vars: &TemplateVars) -> Result<String, String> {
+    let mut rendered : String = String::new();
+    for piece in split_line(line)? {
+        match piece {
+            Piece::Text(x) => rendered.push_str(x),
+            Piece::Var(name) => match vars.lookup(name) {
+                Some(x) => rendered.push_str(&x),
+                None => return Err(format!("variable '{}' is not defined", name)),
+            },
+            Piece::Prompt(name) => rendered.push_str(&prompt_marker(name)),
+        }
+    }
+    Ok(rendered)
+}
+
+//What a '{{ prompt:name }}' is typed as, until the answer replaces it on the target.
+pub fn prompt_marker(name: &str) -> String {
+    format!("@@BUSBI_PROMPT_{}@@", name)
+}
+
+//The names of all prompts in the given text files, sorted and each only once so every value is
+//asked for a single time.
+pub fn collect_prompts(paths: &[&Path]) -> Vec<String> {
+    let mut names : Vec<String> = Vec::new();
+    for path in paths {
+        let contents : String = match read_to_string(path) {
+            Ok(x) => x,
+            Err(_e) => continue,
+        };
+        for line in contents.lines() {
+            for piece in split_line(line).unwrap_or_default() {
+                if let Piece::Prompt(name) = piece {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+//Goes through every text file before anything is created, so a missing variable stops busbi
+//instead of leaving half a script behind. Every problem is printed, not just the first one.
+pub fn check_templates(paths: &[&Path], vars: &TemplateVars) -> Result<(), HelpMessage> {
+    let mut failed : bool = false;
+    for path in paths {
+        //Files that aren't text are typed as base64 and have no placeholders.
+        let contents : String = match read_to_string(path) {
+            Ok(x) => x,
+            Err(_e) => continue,
+        };
+        for (number, line) in contents.lines().enumerate() {
+            if let Err(e) = render_line(line, vars) {
+                println!("Error: {}:{}: {}.", path.display(), number + 1, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return Err(HelpMessage::UndefinedVariable)
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::arguments::{Arguments, SourceEntry};
+use crate::boilerplates::{encrypted_commands_boilerplate, end_boilerplate, removed_dirs_boilerplate, removed_file_boilerplate, restore_boilerplate, restore_policy_boilerplate, start_boilerplate, target_file_path};
+use crate::crypto::encrypt_openssl;
+use crate::ducky::{parse_script, typed_text};
+use crate::handlers::install_relative_path;
+use crate::help_msg::HelpMessage;
+use crate::metadata::Metadata;
+
+// The companion script of every '-d' build, 'payload_uninstall.txt' next to 'payload.txt'. It
+// removes every file and link the script delivers, moves back what '--backup' moved out of the way
+// and sets the execution policy back if '-x' changed it. Directories are removed last and only when
+// they are empty by then, so files someone put there afterwards are kept. With '--encrypt' the
+// commands are encrypted with the passphrase of the payload, the paths would give the files away.
+
+pub fn uninstall_path(destination: &str) -> PathBuf {
+    let path : &Path = Path::new(destination);
+    let stem : String = path.file_stem().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}_uninstall.txt", stem))
+}
+
+//Always for the whole directory, also when the script itself only delivers a part with '--since'.
+pub fn uninstall_script(args: &Arguments, metadata: &Metadata, passphrase: Option<&str>) -> Result<String, HelpMessage> {
+    let Arguments {target_os, keep_prefix, execute, close_window, backup, ..} = *args;
+    let destination : &str = args.install_dest();
+    let mut script : String = start_boilerplate(target_os, false, destination, metadata);
+    let mut commands : String = String::new();
+    let mut targets : Vec<String> = Vec::new();
+    //Every directory below the install directory something was put in.
+    let mut dirs : BTreeSet<PathBuf> = BTreeSet::new();
+    for entry in &args.list_files {
+        let (path, is_dir) : (&Path, bool) = match entry {
+            SourceEntry::File(x) | SourceEntry::Binary(x) | SourceEntry::Symlink(x, _) => (x, false),
+            SourceEntry::EmptyDir(x) => (x, true),
+        };
+        let install_path : PathBuf = install_relative_path(path, &args.source_path, keep_prefix);
+        dirs.extend(install_path.ancestors().skip(if is_dir {0} else {1}).map(|x| x.to_path_buf()));
+        if !is_dir {
+            commands.push_str(&removed_file_boilerplate(target_os, &install_path, destination));
+            targets.push(target_file_path(target_os, path, &install_path, destination, true, false));
+        }
+    }
+    if backup {
+        commands.push_str(&restore_boilerplate(target_os, destination, &targets));
+    }
+    if execute {
+        commands.push_str(&restore_policy_boilerplate(target_os, destination));
+    }
+    //Deepest first, the install directory itself is the empty path and comes last.
+    let mut dirs : Vec<PathBuf> = dirs.into_iter().collect();
+    dirs.sort_by_key(|x| Reverse(x.components().count()));
+    commands.push_str(&removed_dirs_boilerplate(target_os, &dirs, destination));
+    match passphrase {
+        Some(x) => {
+            let text : String = match parse_script(&commands) {
+                Ok(y) => typed_text(&y),
+                Err((line, e)) => {println!("Error: line {} of the uninstall commands: {}.", line, e); return Err(HelpMessage::EncryptionFailed)},
+            };
+            script.push_str(&encrypted_commands_boilerplate(target_os, &encrypt_openssl(text.as_bytes(), x)?));
+        },
+        None => script.push_str(&commands),
+    }
+    script.push_str(&end_boilerplate(target_os, close_window));
+    Ok(script)
+}